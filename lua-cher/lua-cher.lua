@@ -5,18 +5,136 @@ local cher = ffi.load "./libcherenkov.so"
 
 ffi.cdef [[
 typedef void * CherPtr;
+typedef void * CherWorldPtr;
 
 typedef struct {
 	int32_t x;
 	int32_t y;
 } PodVec2;
 
+typedef struct {
+	int32_t restitution;
+	int32_t static_friction;
+	int32_t dynamic_friction;
+	int32_t min_bounce_speed;
+} PodMaterial;
+
+typedef struct {
+	int32_t kind;
+	int32_t t;
+	PodVec2 pos;
+	PodVec2 normal;
+	int32_t obstacle;
+	int32_t feature_kind;
+	int32_t feature_index;
+	int32_t depth;
+	PodVec2 point;
+} PodTraceResult;
+
+typedef struct {
+	int32_t handle;
+	PodVec2 pos;
+	PodVec2 vel;
+	int32_t radius;
+	uint32_t flags;
+	int32_t sleeping;
+} PodParticle;
+
+typedef struct {
+	PodVec2 pos;
+	PodVec2 vel;
+	PodTraceResult contact;
+	int32_t sleeping;
+} PodPrediction;
+
+typedef struct {
+	uint32_t particles;
+	uint32_t traces;
+	uint32_t hits;
+	uint32_t pops;
+	uint32_t max_subticks;
+	uint32_t slips;
+	int32_t max_depth;
+} PodStepStats;
+
+typedef struct {
+	int32_t particle;
+	int32_t impact_speed;
+	int32_t time;
+	PodTraceResult contact;
+} PodContactEvent;
+
+typedef struct {
+	int32_t distance;
+	PodTraceResult contact;
+} PodRaycastHit;
+
 CherPtr cher_new (float radius, PodVec2 player_start);
 void cher_add_polycapsule (CherPtr, int32_t n, PodVec2 * points);
+void cher_add_one_way_polycapsule (CherPtr, int32_t n, PodVec2 * points, PodVec2 up);
 void cher_delete (CherPtr);
 
+CherWorldPtr cher_world_new (float radius);
+void cher_world_add_polycapsule (CherWorldPtr, int32_t n, PodVec2 * points);
+void cher_world_add_one_way_polycapsule (CherWorldPtr, int32_t n, PodVec2 * points, PodVec2 up);
+void cher_world_delete (CherWorldPtr);
+CherPtr cher_new_in_world (CherWorldPtr, PodVec2 player_start);
+
+void cher_set_player_material (CherPtr, PodMaterial);
+void cher_set_obstacle_material (CherPtr, int32_t obstacle, PodMaterial);
+void cher_set_segment_material (CherPtr, int32_t obstacle, int32_t segment, PodMaterial);
+
 void cher_step (CherPtr);
+void cher_step_dt (CherPtr, int32_t dt);
 PodVec2 cher_get_player (CherPtr);
+int32_t cher_add_particle (CherPtr, PodVec2 start);
+int32_t cher_spawn_particle (CherPtr, PodVec2 start, PodVec2 vel, int32_t radius, uint32_t flags);
+int32_t cher_remove_particle (CherPtr, int32_t handle);
+int32_t cher_particle_count (CherPtr);
+int32_t cher_get_particle_handles (CherPtr, int32_t max, int32_t * out);
+PodVec2 cher_get_particle (CherPtr, int32_t handle);
+int32_t cher_get_particle_state (CherPtr, int32_t handle, PodParticle * out);
+void cher_set_particle_flags (CherPtr, int32_t handle, uint32_t flags);
+void cher_set_particle_radius (CherPtr, int32_t handle, int32_t radius);
+
+void cher_trace_batch (CherPtr, int32_t n, const int32_t * start_x, const int32_t * start_y, const int32_t * dir_x, const int32_t * dir_y, PodTraceResult * out);
+
+int32_t cher_raycast (CherPtr, PodVec2 origin, PodVec2 dir, int32_t max_distance, int32_t thin, PodRaycastHit * out);
+int32_t cher_raycast_all (CherPtr, PodVec2 origin, PodVec2 dir, int32_t max_distance, int32_t thin, int32_t max_hits, PodRaycastHit * out);
+int32_t cher_sweep_circle (CherPtr, PodVec2 start, PodVec2 motion, int32_t radius, PodTraceResult * out);
+
+void cher_set_gravity (CherPtr, PodVec2 gravity);
+PodVec2 cher_get_gravity (CherPtr);
+void cher_set_subticks (CherPtr, int32_t subticks);
+int32_t cher_get_subticks (CherPtr);
+void cher_set_restitution (CherPtr, int32_t restitution);
+int32_t cher_get_restitution (CherPtr);
+void cher_set_pop_restitution (CherPtr, int32_t restitution);
+int32_t cher_get_pop_restitution (CherPtr);
+void cher_set_max_speed (CherPtr, int32_t max_speed);
+int32_t cher_get_max_speed (CherPtr);
+void cher_apply_impulse (CherPtr, int32_t handle, PodVec2 impulse);
+void cher_apply_force (CherPtr, int32_t handle, PodVec2 force);
+void cher_set_velocity (CherPtr, int32_t handle, PodVec2 vel);
+int32_t cher_jump (CherPtr, int32_t handle, int32_t speed, int32_t coyote_ticks);
+
+int32_t cher_is_sleeping (CherPtr);
+void cher_wake (CherPtr);
+PodTraceResult cher_get_player_contact (CherPtr);
+
+int32_t cher_predict (CherPtr, int32_t handle, int32_t ticks, PodPrediction * out);
+int32_t cher_predict_launch (CherPtr, int32_t handle, PodVec2 pos, PodVec2 vel, int32_t ticks, PodPrediction * out);
+
+PodStepStats cher_get_stats (CherPtr);
+void cher_reset_stats (CherPtr);
+int32_t cher_drain_events (CherPtr, int32_t max, PodContactEvent * out);
+int32_t cher_take_dropped_events (CherPtr);
+void cher_set_event_capacity (CherPtr, int32_t capacity);
+uint64_t cher_state_hash (CherPtr, int32_t obstacles);
+int32_t cher_snapshot_size (CherPtr);
+int32_t cher_snapshot_write (CherPtr, int32_t n, uint8_t * out);
+int32_t cher_snapshot_read (CherPtr, int32_t n, const char * data);
+int32_t cher_snapshot_read_in_world (CherPtr, CherWorldPtr, int32_t n, const char * data);
 ]]
 
 local scale_den = 1.0
@@ -37,7 +155,7 @@ end
 
 local function new (pos)
 	local ctx = ffi.gc (cher.cher_new (8.0 / scale_den, into_cher_space (pos)), cher.cher_delete)
-
+	
 	local function add_polycapsule (points)
 		local pods = ffi.new ("PodVec2 [?]", #points)
 		
@@ -57,18 +175,197 @@ local function new (pos)
 	return ctx
 end
 
+local function polyline_pods (points)
+	local pods = ffi.new ("PodVec2 [?]", #points)
+	
+	for i = 1, #points do
+		pods [i - 1] = into_cher_space (points [i])
+	end
+	
+	return pods
+end
+
+-- The level from polylines.lua, built once so any number of sims can
+-- share it through new_in_world
+local function new_world ()
+	local world = ffi.gc (cher.cher_world_new (8.0 / scale_den), cher.cher_world_delete)
+	
+	for _, polyline in ipairs (polylines) do
+		cher.cher_world_add_polycapsule (world, #polyline, polyline_pods (polyline))
+	end
+	
+	return world
+end
+
+local function new_in_world (world, pos)
+	return ffi.gc (cher.cher_new_in_world (world, into_cher_space (pos)), cher.cher_delete)
+end
+
 local function get_player (ctx)
 	return cher.cher_get_player (ctx)
 end
 
-local function step (ctx)
-	cher.cher_step (ctx)
+-- Returns a handle for get_particle and remove_particle
+local function add_particle (ctx, pos)
+	return cher.cher_add_particle (ctx, into_cher_space (pos))
+end
+
+local function remove_particle (ctx, handle)
+	return cher.cher_remove_particle (ctx, handle) ~= 0
+end
+
+-- Same as get_player, so it's still in cher space
+local function get_particle (ctx, handle)
+	return cher.cher_get_particle (ctx, handle)
+end
+
+-- dt is in ticks, and defaults to 1
+local function step (ctx, dt)
+	if dt then
+		cher.cher_step_dt (ctx, dt * 65536.0)
+	else
+		cher.cher_step (ctx)
+	end
+end
+
+-- speed is in screen space per tick. Returns true if it jumped.
+local function jump (ctx, handle, speed, coyote_ticks)
+	return cher.cher_jump (ctx, handle, speed * 65536.0 / scale_den, coyote_ticks or 0) ~= 0
+end
+
+local sweep_kinds = { [0] = "clear", "hit", "overlap" }
+
+-- Where a ball of the given radius would stop if it moved by motion.
+-- start and motion are {x, y} in screen space. Returns "clear", "hit"
+-- or "overlap", the fraction of motion it managed, where it stops,
+-- and the normal of whatever it touched.
+local function sweep_circle (ctx, start, motion, radius)
+	local out = ffi.new ("PodTraceResult")
+	local pod_motion = ffi.new ("PodVec2")
+	
+	pod_motion.x = motion [1] * 65536.0 / scale_den
+	pod_motion.y = motion [2] * 65536.0 / scale_den
+	
+	local kind = cher.cher_sweep_circle (ctx, into_cher_space (start), pod_motion, radius * 65536.0 / scale_den, out)
+	
+	if kind == 0 then
+		return sweep_kinds [kind], 1.0, { start [1] + motion [1], start [2] + motion [2] }
+	end
+	
+	local pos = {
+		out.pos.x * scale_den / 65536.0 + 400,
+		out.pos.y * scale_den / 65536.0 + 300,
+	}
+	local normal = { out.normal.x / 65536.0, out.normal.y / 65536.0 }
+	
+	return sweep_kinds [kind], out.t / 65536.0, pos, normal
+end
+
+-- An aiming arc for the player thrown from pos with vel, both {x, y}
+-- in screen space, as a list of {x, y} points one tick apart
+local function predict_launch (ctx, pos, vel, ticks)
+	local out = ffi.new ("PodPrediction [?]", ticks)
+	local pod_vel = ffi.new ("PodVec2")
+	
+	pod_vel.x = vel [1] * 65536.0 / scale_den
+	pod_vel.y = vel [2] * 65536.0 / scale_den
+	
+	local n = cher.cher_predict_launch (ctx, 0, into_cher_space (pos), pod_vel, ticks, out)
+	
+	local points = {}
+	for i = 0, n - 1 do
+		points [#points + 1] = {
+			out [i].pos.x * scale_den / 65536.0 + 400,
+			out [i].pos.y * scale_den / 65536.0 + 300,
+		}
+	end
+	
+	return points
+end
+
+-- Counters for the debug overlay, added up since the last call. Pass
+-- keep = true to read them without resetting.
+local function get_stats (ctx, keep)
+	local s = cher.cher_get_stats (ctx)
+	
+	if not keep then
+		cher.cher_reset_stats (ctx)
+	end
+	
+	return {
+		particles = s.particles,
+		traces = s.traces,
+		hits = s.hits,
+		pops = s.pops,
+		max_subticks = s.max_subticks,
+		slips = s.slips,
+		max_depth = s.max_depth * scale_den / 65536.0,
+	}
+end
+
+-- Returns every queued contact event, oldest first, and how many were
+-- dropped because nobody drained them in time
+local function drain_events (ctx)
+	local events = {}
+	local out = ffi.new ("PodContactEvent [?]", 64)
+	
+	while true do
+		local n = cher.cher_drain_events (ctx, 64, out)
+		
+		for i = 0, n - 1 do
+			local e = out [i]
+			events [#events + 1] = {
+				particle = e.particle,
+				obstacle = e.contact.obstacle,
+				pos = {
+					e.contact.pos.x * scale_den / 65536.0 + 400,
+					e.contact.pos.y * scale_den / 65536.0 + 300,
+				},
+				normal = { e.contact.normal.x / 65536.0, e.contact.normal.y / 65536.0 },
+				impact_speed = e.impact_speed * scale_den / 65536.0,
+				time = e.time / 65536.0,
+			}
+		end
+		
+		if n < 64 then
+			break
+		end
+	end
+	
+	return events, cher.cher_take_dropped_events (ctx)
+end
+
+-- Returns the snapshot as a Lua string, for rewinding or save games
+local function save (ctx)
+	local n = cher.cher_snapshot_size (ctx)
+	local out = ffi.new ("uint8_t [?]", n)
+	
+	cher.cher_snapshot_write (ctx, n, out)
+	
+	return ffi.string (out, n)
+end
+
+-- Returns false if the string isn't a snapshot this build can read
+local function restore (ctx, snapshot)
+	return cher.cher_snapshot_read (ctx, #snapshot, snapshot) == 1
 end
 
 return {
 	new = new,
+	new_world = new_world,
+	new_in_world = new_in_world,
 	step = step,
 	get_player = get_player,
+	add_particle = add_particle,
+	remove_particle = remove_particle,
+	get_particle = get_particle,
+	jump = jump,
+	sweep_circle = sweep_circle,
+	get_stats = get_stats,
+	drain_events = drain_events,
+	save = save,
+	restore = restore,
+	predict_launch = predict_launch,
 	scale_den = scale_den,
 	polylines = polylines,
 }
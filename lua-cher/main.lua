@@ -4,9 +4,9 @@ local FixedTimestep = require "fixed-timestep"
 
 local ctx = Cher.new ({400, 100})
 
-local ctxs = {}
+local particles = {}
 for i = 1, 50 do
-	ctxs [i] = Cher.new {400 + (i - 25) * 2, 100}
+	particles [i] = Cher.add_particle (ctx, {400 + (i - 25) * 2, 100})
 end
 
 local timestep = FixedTimestep (60, 1)
@@ -17,11 +17,11 @@ local steps = 0
 local playing = false
 
 function love.draw ()
-	local function draw_player (ctx)
-		local player = Cher.get_player (ctx)
+	local function draw_particle (handle)
+		local pos = Cher.get_particle (ctx, handle)
 		
-		local x = math.floor (player.x * scale_den / 65536.0) + 400
-		local y = math.floor (player.y * scale_den / 65536.0) + 300
+		local x = math.floor (pos.x * scale_den / 65536.0) + 400
+		local y = math.floor (pos.y * scale_den / 65536.0) + 300
 		
 		--print (x, y)
 		
@@ -43,9 +43,9 @@ function love.draw ()
 		return colors [(i - 1) % 6 + 1]
 	end
 	
-	for i, ctx in ipairs (ctxs) do
+	for i, handle in ipairs (particles) do
 		love.graphics.setColor (get_color (i))
-		draw_player (ctx)
+		draw_particle (handle)
 	end
 	
 	
@@ -79,9 +79,7 @@ end
 function love.update (dt)
 	if playing then
 		timestep:step (dt, function ()
-			for _, ctx in ipairs (ctxs) do
-				Cher.step (ctx)
-			end
+			Cher.step (ctx)
 		end)
 		
 		steps = steps + 1
@@ -0,0 +1,63 @@
+use circle::Circle;
+use fx32::Fx32;
+use ray2::Ray2;
+use vec2::Vec2;
+use wide_line::WideLine;
+
+use std::cmp;
+
+// Axis-aligned bounding box. min and max are both inside.
+#[derive (Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Aabb {
+	pub min: Vec2 <Fx32>,
+	pub max: Vec2 <Fx32>,
+}
+
+impl Aabb {
+	pub fn from_points (a: Vec2 <Fx32>, b: Vec2 <Fx32>) -> Aabb {
+		Aabb {
+			min: Vec2::<Fx32> {
+				x: Fx32 { x: cmp::min (a.x.x, b.x.x) },
+				y: Fx32 { x: cmp::min (a.y.x, b.y.x) },
+			},
+			max: Vec2::<Fx32> {
+				x: Fx32 { x: cmp::max (a.x.x, b.x.x) },
+				y: Fx32 { x: cmp::max (a.y.x, b.y.x) },
+			},
+		}
+	}
+	
+	pub fn from_circle (circle: &Circle) -> Aabb {
+		Aabb::from_points (circle.center, circle.center).expand (circle.radius)
+	}
+	
+	pub fn from_line (line: &WideLine) -> Aabb {
+		Aabb::from_points (line.start, line.end).expand (line.radius)
+	}
+	
+	// Everything the ray touches between t = 0 and t = 1
+	pub fn from_ray (ray: &Ray2) -> Aabb {
+		Aabb::from_points (ray.start, ray.start + ray.get_dir ())
+	}
+	
+	pub fn expand (&self, r: Fx32) -> Aabb {
+		let r = Vec2::<Fx32> { x: r, y: r };
+		
+		Aabb {
+			min: self.min - r,
+			max: self.max + r,
+		}
+	}
+	
+	pub fn union (&self, o: &Aabb) -> Aabb {
+		Aabb {
+			min: Aabb::from_points (self.min, o.min).min,
+			max: Aabb::from_points (self.max, o.max).max,
+		}
+	}
+	
+	pub fn overlaps (&self, o: &Aabb) -> bool {
+		self.min.x <= o.max.x && o.min.x <= self.max.x &&
+		self.min.y <= o.max.y && o.min.y <= self.max.y
+	}
+}
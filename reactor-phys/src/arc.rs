@@ -1,6 +1,8 @@
 use circle::Circle;
+use contact::Contact;
 use fx32::Fx32;
 use fx32::Fx32Small;
+use material::Material;
 use vec2::Vec2;
 use raytraceresult::Ray2TraceResult;
 
@@ -25,6 +27,9 @@ the joint. (The upper-left quadrant in this ascii diagram)
 pub struct Arc {
 	pub circle: Circle,
 	pub rejected_normals: [Vec2 <Fx32Small>; 2],
+	// Copied from the WideLines of a one-way PolyCapsule
+	pub one_way: Option <Vec2 <Fx32Small>>,
+	pub material: Material,
 }
 
 impl Arc {
@@ -35,19 +40,27 @@ impl Arc {
 	// Oh won't you be my neighbor
 	pub fn new2 (circle: &Circle, neighbor0: Vec2 <Fx32>, neighbor1: Vec2 <Fx32>) -> Arc 
 	{
-		let to_0 = (neighbor0 - circle.center) * Fx32::from_q (1, 256);
-		let to_1 = (neighbor1 - circle.center) * Fx32::from_q (1, 256);
+		let to_0 = neighbor0 - circle.center;
+		let to_1 = neighbor1 - circle.center;
 		
 		Arc {
 			circle: *circle,
 			rejected_normals: [
-				to_0.normalized (),
-				to_1.normalized (),
+				to_0.normalized_64 (),
+				to_1.normalized_64 (),
 			],
+			one_way: None,
+			material: Material::default (),
 		}
 	}
 	
 	pub fn filter_normal (&self, v: Vec2 <Fx32Small>) -> bool {
+		if let Some (allowed) = self.one_way {
+			if v * allowed <= 0 {
+				return false;
+			}
+		}
+		
 		return true;
 		/*
 		if v * self.rejected_normals [0] > 0 {
@@ -66,17 +79,17 @@ impl Arc {
 	{
 		match input {
 			Ray2TraceResult::Miss => return Ray2TraceResult::Miss,
-			Ray2TraceResult::Pop (__, normal) => {
-				if self.filter_normal (normal) {
-					return input;
+			Ray2TraceResult::Pop (c) => {
+				if self.filter_normal (c.normal) {
+					return Ray2TraceResult::Pop (Contact { material: self.material, .. c });
 				}
 				else {
 					return Ray2TraceResult::Miss;
 				}
 			},
-			Ray2TraceResult::Hit (_, __, normal) => {
-				if self.filter_normal (normal) {
-					return input;
+			Ray2TraceResult::Hit (c) => {
+				if self.filter_normal (c.normal) {
+					return Ray2TraceResult::Hit (Contact { material: self.material, .. c });
 				}
 				else {
 					return Ray2TraceResult::Miss;
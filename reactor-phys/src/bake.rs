@@ -0,0 +1,177 @@
+use aabb::Aabb;
+use arc::Arc;
+use fx32::Fx32;
+use fx32::Fx32Small;
+use material::Material;
+use polycapsule::PolyCapsule;
+use ray2::Ray2;
+use raytrace::ray_trace_baked_arc;
+use raytrace::ray_trace_baked_line;
+use raytraceresult::Ray2TraceResult;
+use vec2::Vec2;
+use wide_line::WideLine;
+
+use std::cmp;
+
+/*
+The bake turns PolyCapsules into a flat, read-only list of features
+with everything the tracer needs already worked out in 64 bits.
+Tracing never has to normalize or take a square root of the
+obstacle's geometry.
+*/
+
+#[derive (Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FeatureKind {
+	Arc,
+	Line,
+}
+
+// Which piece of which obstacle a feature came from
+#[derive (Clone, Copy, Debug, Eq, PartialEq)]
+pub struct FeatureRef {
+	pub obstacle: u32,
+	pub kind: FeatureKind,
+	pub index: u32,
+}
+
+#[derive (Clone, Copy, Debug)]
+pub struct BakedLine {
+	pub start: Vec2 <Fx32>,
+	pub end: Vec2 <Fx32>,
+	pub radius: Fx32,
+	// Unit vector from start to end
+	pub tangent: Vec2 <Fx32Small>,
+	// tangent rotated 90 degrees anti-clockwise
+	pub normal: Vec2 <Fx32Small>,
+	// Distance from start to end
+	pub length: Fx32,
+	pub one_way: Option <Vec2 <Fx32Small>>,
+	pub material: Material,
+}
+
+impl BakedLine {
+	pub fn new (line: &WideLine) -> BakedLine {
+		let diff = line.end - line.start;
+		let tangent = diff.normalized_64 ();
+		
+		BakedLine {
+			start: line.start,
+			end: line.end,
+			radius: line.radius,
+			tangent: tangent,
+			normal: Vec2::<Fx32Small> {
+				x: (-tangent.y.x).to_small (),
+				y: tangent.x,
+			},
+			length: diff.length_64 (),
+			one_way: line.one_way,
+			material: line.material,
+		}
+	}
+	
+	// n is the normal facing the ray, already flipped
+	pub fn accepts_normal (&self, n: Vec2 <Fx32Small>) -> bool {
+		match self.one_way {
+			Some (allowed) => n * allowed > 0,
+			None => true,
+		}
+	}
+}
+
+#[derive (Clone, Copy, Debug)]
+pub struct BakedArc {
+	// The wedge (rejected_normals) is already 64-bit from Arc::new2
+	pub arc: Arc,
+}
+
+impl BakedArc {
+	pub fn new (arc: &Arc) -> BakedArc {
+		BakedArc {
+			arc: *arc,
+		}
+	}
+}
+
+#[derive (Clone, Copy, Debug)]
+pub enum Shape {
+	Arc (BakedArc),
+	Line (BakedLine),
+}
+
+#[derive (Clone, Copy, Debug)]
+pub struct Feature {
+	pub id: FeatureRef,
+	// Everything the feature covers, at its full radius
+	pub bounds: Aabb,
+	pub shape: Shape,
+}
+
+impl Feature {
+	pub fn trace (&self, ray: &Ray2) -> Ray2TraceResult {
+		let result = match self.shape {
+			Shape::Arc (ref arc) => ray_trace_baked_arc (ray, arc),
+			Shape::Line (ref line) => {
+				// Quick AABB rejection
+				if ! Aabb::from_ray (ray).overlaps (&self.bounds) {
+					return Ray2TraceResult::Miss;
+				}
+				
+				ray_trace_baked_line (ray, line)
+			},
+		};
+		
+		result.with_feature (self.id)
+	}
+	
+	// The same feature pulled in by amount all round, stopping at a
+	// bare line or point. A negative amount grows it.
+	pub fn shrunk (&self, amount: Fx32) -> Feature {
+		let shrink = |r: Fx32| Fx32 { x: cmp::max ((r - amount).x, 0) };
+		// Shrinking can leave the bounds as they were, which is still
+		// conservative
+		let grow = |b: Aabb| if amount < 0 { b.expand (-amount) } else { b };
+		
+		let shape = match self.shape {
+			Shape::Arc (arc) => {
+				let mut arc = arc;
+				arc.arc.circle.radius = shrink (arc.arc.circle.radius);
+				Shape::Arc (arc)
+			},
+			Shape::Line (line) => Shape::Line (BakedLine {
+				radius: shrink (line.radius),
+				.. line
+			}),
+		};
+		
+		Feature {
+			bounds: grow (self.bounds),
+			shape: shape,
+			.. *self
+		}
+	}
+}
+
+// Features come out obstacle by obstacle, arcs then lines, which is
+// the order the old brute-force loop visited them in
+pub fn bake (obstacles: &[PolyCapsule]) -> Vec <Feature> {
+	let mut features = vec! [];
+	
+	for (obstacle, capsule) in obstacles.iter ().enumerate () {
+		for (i, arc) in capsule.arcs.iter ().enumerate () {
+			features.push (Feature {
+				id: FeatureRef { obstacle: obstacle as u32, kind: FeatureKind::Arc, index: i as u32 },
+				bounds: Aabb::from_circle (&arc.circle),
+				shape: Shape::Arc (BakedArc::new (arc)),
+			});
+		}
+		for (i, line) in capsule.lines.iter ().enumerate () {
+			features.push (Feature {
+				id: FeatureRef { obstacle: obstacle as u32, kind: FeatureKind::Line, index: i as u32 },
+				bounds: Aabb::from_line (line),
+				shape: Shape::Line (BakedLine::new (line)),
+			});
+		}
+	}
+	
+	features
+}
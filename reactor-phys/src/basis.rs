@@ -12,12 +12,8 @@ impl Basis2 {
 	// Constructs a basis to ray space
 	// Such that the ray is the X axis
 	pub fn new (ray: &Ray2) -> Basis2 {
-		let basis_x_big = if ray.get_length () == 0 {
-			ray.get_dir ()
-		}
-		else {
-			ray.get_dir () / ray.get_length ()
-		};
+		// Very short rays would divide by zero in 32 bits
+		let basis_x_big: Vec2 <Fx32> = ray.get_dir ().normalized_64 ().into ();
 		
 		Basis2 {
 			x: basis_x_big.to_small (),
@@ -0,0 +1,166 @@
+use aabb::Aabb;
+use broadphase::Broadphase;
+use broadphase::ray_slop;
+use fx32::Fx32;
+use ray2::Ray2;
+use raytraceresult::Ray2TraceResult;
+use raytraceresult::Resolver;
+use vec2::Vec2;
+
+use std::cmp;
+
+/*
+Many rays stored as a struct of arrays.
+
+Every field is raw Fx32 bits, borrowed straight from the caller, so
+anything that touches all the rays at once is a plain loop over i32
+slices that LLVM can vectorize. For particles, start is the position
+and dir is the velocity.
+*/
+#[derive (Clone, Copy, Debug)]
+pub struct RayBatch <'a> {
+	pub start_x: &'a [i32],
+	pub start_y: &'a [i32],
+	pub dir_x: &'a [i32],
+	pub dir_y: &'a [i32],
+}
+
+// Rays are traced this many at a time, so a chunk's boxes fit in
+// cache and nearby rays share the broadphase lookup
+const CHUNK: usize = 64;
+
+impl <'a> RayBatch <'a> {
+	// All four must be the same length
+	pub fn new (start_x: &'a [i32], start_y: &'a [i32], dir_x: &'a [i32], dir_y: &'a [i32]) -> RayBatch <'a> {
+		let n = start_x.len ();
+		assert! (start_y.len () == n && dir_x.len () == n && dir_y.len () == n);
+		
+		RayBatch {
+			start_x: start_x,
+			start_y: start_y,
+			dir_x: dir_x,
+			dir_y: dir_y,
+		}
+	}
+	
+	pub fn len (&self) -> usize {
+		self.start_x.len ()
+	}
+	
+	pub fn is_empty (&self) -> bool {
+		self.start_x.is_empty ()
+	}
+	
+	pub fn ray (&self, i: usize) -> Ray2 {
+		Ray2::new (
+			Vec2::<Fx32> { x: Fx32 { x: self.start_x [i] }, y: Fx32 { x: self.start_y [i] } },
+			Vec2::<Fx32> { x: Fx32 { x: self.dir_x [i] }, y: Fx32 { x: self.dir_y [i] } },
+		)
+	}
+	
+	// Swept boxes of every ray, same as broadphase::ray_bounds
+	fn bounds (&self) -> (Vec <i32>, Vec <i32>, Vec <i32>, Vec <i32>) {
+		let n = self.len ();
+		let slop = ray_slop ().x;
+		
+		let mut min_x = vec! [0; n];
+		let mut min_y = vec! [0; n];
+		let mut max_x = vec! [0; n];
+		let mut max_y = vec! [0; n];
+		
+		for i in 0..n {
+			let end = self.start_x [i] + self.dir_x [i];
+			min_x [i] = cmp::min (self.start_x [i], end) - slop;
+			max_x [i] = cmp::max (self.start_x [i], end) + slop;
+		}
+		for i in 0..n {
+			let end = self.start_y [i] + self.dir_y [i];
+			min_y [i] = cmp::min (self.start_y [i], end) - slop;
+			max_y [i] = cmp::max (self.start_y [i], end) + slop;
+		}
+		
+		(min_x, min_y, max_x, max_y)
+	}
+}
+
+// Adds the same velocity to every ray, e.g. gravity. Wraps around on
+// overflow instead of panicking.
+pub fn accelerate (dir_x: &mut [i32], dir_y: &mut [i32], dv: Vec2 <Fx32>) {
+	for x in dir_x.iter_mut () {
+		*x = x.wrapping_add (dv.x.x);
+	}
+	for y in dir_y.iter_mut () {
+		*y = y.wrapping_add (dv.y.x);
+	}
+}
+
+/*
+Traces every ray in the batch against the world. out is cleared and
+gets one result per ray, identical to calling world.trace on each.
+
+Goes feature by feature instead of ray by ray. Each feature is
+checked against a whole chunk of ray boxes in one branch-free loop,
+and only the rays that overlap it are traced. Resolver doesn't care
+what order results come in, so the answers are the same.
+
+Works best when rays next to each other in the batch are near each
+other in the world, since each chunk looks up everything its rays'
+boxes span.
+*/
+pub fn trace_batch (world: &Broadphase, batch: &RayBatch, out: &mut Vec <Ray2TraceResult>) {
+	out.clear ();
+	out.reserve (batch.len ());
+	
+	let (min_x, min_y, max_x, max_y) = batch.bounds ();
+	let mut candidates = vec! [];
+	let mut rays = Vec::with_capacity (CHUNK);
+	let mut overlaps = [false; CHUNK];
+	let mut resolvers = [Resolver::new (); CHUNK];
+	
+	let mut start = 0;
+	while start < batch.len () {
+		let end = cmp::min (start + CHUNK, batch.len ());
+		let n = end - start;
+		let (min_x, min_y) = (&min_x [start..end], &min_y [start..end]);
+		let (max_x, max_y) = (&max_x [start..end], &max_y [start..end]);
+		
+		// Anything a ray in the chunk could touch overlaps this
+		let chunk_bounds = Aabb {
+			min: Vec2::<Fx32> {
+				x: Fx32 { x: min_x.iter ().fold (i32::max_value (), |a, b| cmp::min (a, *b)) },
+				y: Fx32 { x: min_y.iter ().fold (i32::max_value (), |a, b| cmp::min (a, *b)) },
+			},
+			max: Vec2::<Fx32> {
+				x: Fx32 { x: max_x.iter ().fold (i32::min_value (), |a, b| cmp::max (a, *b)) },
+				y: Fx32 { x: max_y.iter ().fold (i32::min_value (), |a, b| cmp::max (a, *b)) },
+			},
+		};
+		world.candidates (&chunk_bounds, &mut candidates);
+		
+		rays.clear ();
+		rays.extend ((start..end).map (|i| batch.ray (i)));
+		for r in resolvers [..n].iter_mut () {
+			*r = Resolver::new ();
+		}
+		
+		for f in candidates.iter () {
+			let feature = &world.features [*f as usize];
+			let b = &feature.bounds;
+			
+			// Same test as Aabb::overlaps, without branches
+			for j in 0..n {
+				overlaps [j] = (min_x [j] <= b.max.x.x) & (b.min.x.x <= max_x [j]) &
+					(min_y [j] <= b.max.y.x) & (b.min.y.x <= max_y [j]);
+			}
+			
+			for j in 0..n {
+				if overlaps [j] {
+					resolvers [j] = resolvers [j].add (feature.trace (&rays [j]));
+				}
+			}
+		}
+		
+		out.extend (resolvers [..n].iter ().map (|r| r.finish ()));
+		start = end;
+	}
+}
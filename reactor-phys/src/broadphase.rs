@@ -0,0 +1,214 @@
+use aabb::Aabb;
+use bake::Feature;
+use bake::bake;
+use fx32::Fx32;
+use fx32::FRACTIONAL_BITS;
+use polycapsule::PolyCapsule;
+use ray2::Ray2;
+use raytraceresult::Ray2TraceResult;
+use raytraceresult::Resolver;
+use vec2::Vec2;
+
+use std::cmp;
+
+// Cells are 32 units on a side, unless that would take too many
+const CELL_BITS: i32 = FRACTIONAL_BITS + 5;
+
+// Far-apart obstacles get bigger cells instead of a huge grid
+const MAX_CELLS: i64 = 1 << 16;
+
+// The box searched for a ray, with a little slack for rounding in
+// the narrow phase
+pub fn ray_bounds (ray: &Ray2) -> Aabb {
+	Aabb::from_ray (ray).expand (ray_slop ())
+}
+
+pub fn ray_slop () -> Fx32 {
+	Fx32::from_int (1)
+}
+
+/*
+Uniform grid over every arc and line in the world.
+
+Each cell keeps a sorted list of feature indices. Results are folded
+with a Resolver, which doesn't care about order, so the grid finds
+exactly what a brute-force loop over every feature finds.
+*/
+#[derive (Clone)]
+pub struct Broadphase {
+	pub features: Vec <Feature>,
+	origin: Vec2 <Fx32>,
+	// Cells are 1 << cell_bits raw Fx32 units on a side
+	cell_bits: i32,
+	width: i32,
+	height: i32,
+	cells: Vec <Vec <u32>>,
+}
+
+impl Broadphase {
+	pub fn new (obstacles: &[PolyCapsule]) -> Broadphase {
+		Broadphase::from_features (bake (obstacles))
+	}
+	
+	pub fn from_features (features: Vec <Feature>) -> Broadphase {
+		let mut grid = Broadphase {
+			features: features,
+			origin: Vec2::<Fx32> { x: Fx32::from_int (0), y: Fx32::from_int (0) },
+			cell_bits: CELL_BITS,
+			width: 0,
+			height: 0,
+			cells: vec! [],
+		};
+		
+		if grid.features.is_empty () {
+			return grid;
+		}
+		
+		let world = grid.features.iter ().fold (grid.features [0].bounds, |b, f| b.union (&f.bounds));
+		
+		// In 64 bits, since the span can be wider than an i32
+		let span_x = world.max.x.x as i64 - world.min.x.x as i64;
+		let span_y = world.max.y.x as i64 - world.min.y.x as i64;
+		
+		while ((span_x >> grid.cell_bits) + 1) * ((span_y >> grid.cell_bits) + 1) > MAX_CELLS {
+			grid.cell_bits += 1;
+		}
+		
+		grid.origin = world.min;
+		grid.width = ((span_x >> grid.cell_bits) + 1) as i32;
+		grid.height = ((span_y >> grid.cell_bits) + 1) as i32;
+		grid.cells = vec! [vec! []; (grid.width * grid.height) as usize];
+		
+		for i in 0..grid.features.len () {
+			let (x0, y0, x1, y1) = grid.cell_range (&grid.features [i].bounds);
+			
+			for y in y0..y1 + 1 {
+				for x in x0..x1 + 1 {
+					let cell = (y * grid.width + x) as usize;
+					grid.cells [cell].push (i as u32);
+				}
+			}
+		}
+		
+		grid
+	}
+	
+	// Inclusive range of cells touched by a box, clamped to the grid
+	fn cell_range (&self, bounds: &Aabb) -> (i32, i32, i32, i32) {
+		let cell = |v: Fx32, origin: Fx32, count: i32| {
+			let c = (v.x as i64 - origin.x as i64) >> self.cell_bits;
+			cmp::max (0, cmp::min (count as i64 - 1, c)) as i32
+		};
+		
+		(
+			cell (bounds.min.x, self.origin.x, self.width),
+			cell (bounds.min.y, self.origin.y, self.height),
+			cell (bounds.max.x, self.origin.x, self.width),
+			cell (bounds.max.y, self.origin.y, self.height),
+		)
+	}
+	
+	// Fills out with the sorted, de-duplicated indices of every
+	// feature whose bounds overlap the given box
+	pub fn candidates (&self, bounds: &Aabb, out: &mut Vec <u32>) {
+		out.clear ();
+		
+		if self.features.is_empty () {
+			return;
+		}
+		
+		let (x0, y0, x1, y1) = self.cell_range (bounds);
+		
+		for y in y0..y1 + 1 {
+			for x in x0..x1 + 1 {
+				let cell = &self.cells [(y * self.width + x) as usize];
+				
+				out.extend (cell.iter ().filter (|i| self.features [**i as usize].bounds.overlaps (bounds)));
+			}
+		}
+		
+		out.sort ();
+		out.dedup ();
+	}
+	
+	// filter sees each result before it's folded in. Allocates a
+	// buffer for the candidates every call, so anything tracing lots of
+	// rays should use trace_in or trace_shrunk_in instead.
+	pub fn trace <F> (&self, ray: &Ray2, filter: F) -> Ray2TraceResult 
+	where F: Fn (Ray2TraceResult) -> Ray2TraceResult 
+	{
+		let mut scratch = vec! [];
+		
+		self.trace_in (ray, &ray_bounds (ray), &mut scratch, filter)
+	}
+	
+	// Same as trace, but the caller supplies the ray's bounds and a
+	// buffer for candidates, so a batch of rays can share them.
+	// bounds must match ray_bounds for the results to match trace.
+	pub fn trace_in <F> (&self, ray: &Ray2, bounds: &Aabb, scratch: &mut Vec <u32>, filter: F) -> Ray2TraceResult 
+	where F: Fn (Ray2TraceResult) -> Ray2TraceResult 
+	{
+		self.candidates (bounds, scratch);
+		
+		scratch.iter ()
+		.map (|i| filter (self.features [*i as usize].trace (ray)))
+		.fold (Resolver::new (), Resolver::add)
+		.finish ()
+	}
+	
+	// Same as trace, but against every feature shrunk by shrink, for
+	// particles smaller or bigger than the radius the obstacles were
+	// inflated by. A shrink of 0 is exactly trace.
+	pub fn trace_shrunk <F> (&self, ray: &Ray2, shrink: Fx32, filter: F) -> Ray2TraceResult 
+	where F: Fn (Ray2TraceResult) -> Ray2TraceResult 
+	{
+		let mut scratch = vec! [];
+		
+		self.trace_shrunk_in (ray, shrink, &mut scratch, filter)
+	}
+	
+	// Same as trace_shrunk, but reuses scratch for the candidates
+	pub fn trace_shrunk_in <F> (&self, ray: &Ray2, shrink: Fx32, scratch: &mut Vec <u32>, filter: F) -> Ray2TraceResult 
+	where F: Fn (Ray2TraceResult) -> Ray2TraceResult 
+	{
+		let mut resolver = Resolver::new ();
+		
+		self.trace_each_shrunk (ray, shrink, scratch, |result| {
+			resolver = resolver.add (filter (result));
+		});
+		
+		resolver.finish ()
+	}
+	
+	// Calls f with the result from every feature near the ray, shrunk
+	// like trace_shrunk, instead of folding them into one
+	pub fn trace_each_shrunk <F> (&self, ray: &Ray2, shrink: Fx32, scratch: &mut Vec <u32>, mut f: F) 
+	where F: FnMut (Ray2TraceResult) 
+	{
+		// Growing pushes features out of the cells they were filed under
+		let bounds = ray_bounds (ray).expand (Fx32 { x: cmp::max (-shrink.x, 0) });
+		
+		self.candidates (&bounds, scratch);
+		
+		for i in scratch.iter () {
+			let feature = &self.features [*i as usize];
+			
+			if shrink == Fx32::from_int (0) {
+				f (feature.trace (ray));
+			}
+			else {
+				f (feature.shrunk (shrink).trace (ray));
+			}
+		}
+	}
+	
+	// Same as trace, but tests every feature
+	pub fn trace_brute_force <F> (&self, ray: &Ray2, filter: F) -> Ray2TraceResult 
+	where F: Fn (Ray2TraceResult) -> Ray2TraceResult 
+	{
+		self.features.iter ()
+		.map (|f| filter (f.trace (ray)))
+		.fold (Resolver::new (), Resolver::add)
+		.finish ()
+	}
+}
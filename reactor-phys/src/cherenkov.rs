@@ -1,18 +1,350 @@
+use bake::FeatureKind;
+use batch::RayBatch;
+use batch::trace_batch;
+use broadphase::Broadphase;
 use circle::Circle;
+use config::PhysicsConfig;
+use event::ContactEvent;
+use event::EventQueue;
 use fx32::Fx32;
+use material::Material;
+use particle::Particle;
+use particle::MAX_AIR_TIME;
+use particle::is_ground;
 use polycapsule::PolyCapsule;
-use raytrace::*;
+use query::RaycastHit;
+use query::Sweep;
+use query::raycast;
+use query::raycast_all;
+use query::sweep;
 use ray2::Ray2;
 use raytraceresult::Ray2TraceResult;
+use snapshot::SnapshotError;
+use snapshot::read_snapshot;
+use snapshot::read_snapshot_in_world;
+use snapshot::write_snapshot;
+use statehash::state_hash;
+use solver::step_particle;
+use stats::StepStats;
 use vec2::Vec2;
+use world::World;
 
+use std::cmp;
 use std::mem::transmute;
 use std::slice;
+use std::sync;
+
+#[cfg (feature = "parallel")]
+use rayon::prelude::*;
 
 pub struct CherenkovSim {
-	pub obstacles: Vec <PolyCapsule>,
-	pub player: Ray2,
-	pub radius: Fx32,
+	// Possibly shared with other sims. Use world_mut to change it.
+	pub world: sync::Arc <World>,
+	// Sorted by handle, which is the order they were added in and the
+	// order they're stepped in. Handle 0 is the player.
+	pub particles: Vec <Particle>,
+	pub next_handle: u32,
+	pub config: PhysicsConfig,
+	// Added up over every step since the last reset_stats
+	pub stats: StepStats,
+	// Contacts from every step, oldest first, until the game drains them
+	pub events: EventQueue,
+}
+
+// Enough for a few contacts per particle per tick in most scenes
+pub const DEFAULT_EVENT_CAPACITY: usize = 256;
+
+impl CherenkovSim {
+	// Builds the world's broadphase if it isn't already. Build a world
+	// before sharing it, or each sim will build its own copy.
+	pub fn new (mut world: sync::Arc <World>) -> CherenkovSim {
+		if ! world.is_built () {
+			sync::Arc::make_mut (&mut world).build ();
+		}
+		
+		CherenkovSim {
+			world: world,
+			particles: vec! [],
+			next_handle: 0,
+			config: PhysicsConfig::default (),
+			stats: StepStats::default (),
+			events: EventQueue::new (DEFAULT_EVENT_CAPACITY),
+		}
+	}
+	
+	pub fn radius (&self) -> Fx32 {
+		self.world.radius
+	}
+	
+	// Copies the world first if another sim is sharing it, so this sim
+	// is the only one that sees the changes. Call obstacle_changed
+	// after changing an obstacle.
+	pub fn world_mut (&mut self) -> &mut World {
+		sync::Arc::make_mut (&mut self.world)
+	}
+	
+	pub fn add_obstacle (&mut self, capsule: PolyCapsule) {
+		self.world_mut ().add_obstacle (capsule);
+		let i = self.world.obstacles.len () - 1;
+		self.obstacle_changed (i);
+	}
+	
+	// Rebuilds the broadphase, and wakes up anything that might have
+	// been resting on obstacle i. For big levels, fill a World first
+	// instead of rebuilding once per obstacle.
+	pub fn obstacle_changed (&mut self, i: usize) {
+		{
+			let world = self.world_mut ();
+			world.broadphase = None;
+			world.build ();
+		}
+		
+		let (min, max) = self.world.obstacles [i].bounds ();
+		let radius = self.world.radius;
+		
+		for particle in self.particles.iter_mut () {
+			// Particles resting on top are up to a radius outside the
+			// bounds
+			let margin = Fx32 { x: cmp::max (radius.x, particle.radius.x) };
+			let pos = particle.ray.start;
+			
+			if pos.x >= min.x - margin && pos.x <= max.x + margin && pos.y >= min.y - margin && pos.y <= max.y + margin {
+				particle.wake ();
+			}
+		}
+	}
+	
+	// Returns the new particle's handle
+	pub fn add_particle (&mut self, particle: Particle) -> u32 {
+		let handle = self.next_handle;
+		self.next_handle += 1;
+		
+		self.particles.push (Particle {
+			handle: handle,
+			.. particle
+		});
+		
+		handle
+	}
+	
+	// Returns false if there was no such particle. The player can't be
+	// removed.
+	pub fn remove_particle (&mut self, handle: u32) -> bool {
+		if handle == 0 {
+			return false;
+		}
+		
+		match self.particles.binary_search_by_key (&handle, |p| p.handle) {
+			Ok (i) => {
+				self.particles.remove (i);
+				true
+			},
+			Err (_) => false,
+		}
+	}
+	
+	pub fn particle (&self, handle: u32) -> Option <&Particle> {
+		self.particles.binary_search_by_key (&handle, |p| p.handle).ok ().map (move |i| &self.particles [i])
+	}
+	
+	pub fn particle_mut (&mut self, handle: u32) -> Option <&mut Particle> {
+		match self.particles.binary_search_by_key (&handle, |p| p.handle) {
+			Ok (i) => Some (&mut self.particles [i]),
+			Err (_) => None,
+		}
+	}
+	
+	pub fn set_gravity (&mut self, gravity: Vec2 <Fx32>) {
+		if gravity != self.config.gravity {
+			self.config.gravity = gravity;
+			
+			for particle in self.particles.iter_mut () {
+				particle.wake ();
+			}
+		}
+	}
+	
+	// Where a circle of the given radius would stop if it moved by
+	// motion. Obstacles are already inflated by the world's radius, so
+	// they only need to make up the difference.
+	pub fn sweep_circle (&self, start: Vec2 <Fx32>, motion: Vec2 <Fx32>, radius: Fx32) -> Sweep {
+		let shrink = self.radius () - radius;
+		
+		sweep (self.broadphase (), start, motion, shrink)
+	}
+	
+	// Where a particle would go over the next few ticks, without
+	// changing anything. out gets its state after each tick, the same
+	// bits step would give if nothing else changed in the meantime.
+	pub fn predict (&self, particle: &Particle, ticks: u32, out: &mut Vec <Particle>) {
+		let world = self.broadphase ();
+		
+		let mut particle = *particle;
+		let mut scratch = vec! [];
+		let mut events = vec! [];
+		out.clear ();
+		
+		for _ in 0..ticks {
+			step_one (world, &mut scratch, &self.config, self.world.radius, Fx32::from_int (1), &mut particle, &mut events);
+			events.clear ();
+			out.push (particle);
+		}
+	}
+	
+	pub fn reset_stats (&mut self) {
+		self.stats = StepStats::default ();
+	}
+	
+	// Always built, since new and obstacle_changed build it
+	pub fn broadphase (&self) -> &Broadphase {
+		self.world.broadphase.as_ref ().expect ("Obstacles changed without calling obstacle_changed")
+	}
+	
+	pub fn step (&mut self) {
+		self.step_dt (Fx32::from_int (1));
+	}
+	
+	// Advances by dt ticks. Anything over a tick is split into whole
+	// ticks and the rest, since the tracer can't take rays much longer
+	// than a tick. Forces push for all of dt, however it's split, so
+	// the same force gives the same push whatever dt the game picks.
+	pub fn step_dt (&mut self, dt: Fx32) {
+		let one = Fx32::from_int (1);
+		let mut remaining = dt;
+		
+		// Each tick clears the forces, so put them back for the next
+		let forces: Vec <Vec2 <Fx32>> = if dt > one {
+			self.particles.iter ().map (|p| p.force).collect ()
+		}
+		else {
+			vec! []
+		};
+		
+		while remaining > 0 {
+			let dt = Fx32 { x: cmp::min (remaining.x, one.x) };
+			self.step_once (dt);
+			remaining = remaining - dt;
+			
+			if remaining > 0 {
+				for (p, force) in self.particles.iter_mut ().zip (forces.iter ()) {
+					p.force = *force;
+				}
+			}
+		}
+		
+		// Anything that fell asleep partway through doesn't keep it
+		// for later
+		for p in self.particles.iter_mut () {
+			p.force = Vec2::<Fx32> { x: Fx32::from_int (0), y: Fx32::from_int (0) };
+		}
+	}
+	
+	#[cfg (feature = "parallel")]
+	fn step_once (&mut self, dt: Fx32) {
+		self.step_parallel (dt);
+	}
+	
+	#[cfg (not (feature = "parallel"))]
+	fn step_once (&mut self, dt: Fx32) {
+		self.step_serial (dt);
+	}
+	
+	// dt is at most 1
+	pub fn step_serial (&mut self, dt: Fx32) {
+		let world = self.world.broadphase.as_ref ().unwrap ();
+		let config = &self.config;
+		let radius = self.world.radius;
+		
+		let mut scratch = vec! [];
+		let mut events = vec! [];
+		
+		let stats = self.particles.iter_mut ()
+		.map (|particle| step_one (world, &mut scratch, config, radius, dt, particle, &mut events))
+		.fold (StepStats::default (), StepStats::add);
+		
+		self.stats = self.stats.add (stats);
+		
+		for e in events {
+			self.events.push (e);
+		}
+	}
+	
+	// Particles never touch each other, so they can all step at once.
+	// Gives exactly the same bits as step_serial.
+	#[cfg (feature = "parallel")]
+	pub fn step_parallel (&mut self, dt: Fx32) {
+		let world = self.world.broadphase.as_ref ().unwrap ();
+		let config = &self.config;
+		let radius = self.world.radius;
+		
+		// Reducing keeps everything in order, so the events come out
+		// the same as step_serial's too. Each particle gets its own
+		// scratch, since rayon can't hand one out per thread here, but
+		// it's still only one per tick instead of one per subtick.
+		let (stats, events) = self.particles.par_iter_mut ()
+		.map (|particle| {
+			let mut scratch = vec! [];
+			let mut events = vec! [];
+			let stats = step_one (world, &mut scratch, config, radius, dt, particle, &mut events);
+			(stats, events)
+		})
+		.reduce_with (|(a, mut a_events), (b, b_events)| {
+			a_events.extend (b_events);
+			(a.add (b), a_events)
+		})
+		.unwrap_or_default ();
+		
+		self.stats = self.stats.add (stats);
+		
+		for e in events {
+			self.events.push (e);
+		}
+	}
+}
+
+// radius is what the obstacles were inflated by. Contacts are added
+// to events.
+fn step_one (world: &Broadphase, scratch: &mut Vec <u32>, config: &PhysicsConfig, radius: Fx32, dt: Fx32, particle: &mut Particle, events: &mut Vec <ContactEvent>) -> StepStats {
+	let mut stats = StepStats::default ();
+	
+	if particle.sleeping || particle.frozen () {
+		return stats;
+	}
+	
+	let mut contact = Ray2TraceResult::Miss;
+	let mut ground = None;
+	let shrink = radius - particle.radius;
+	let handle = particle.handle;
+	
+	if particle.force != (Vec2::<Fx32> { x: Fx32::from_int (0), y: Fx32::from_int (0) }) {
+		let vel = particle.ray.get_dir () + particle.force.scale_64 (dt);
+		particle.ray = Ray2::new (particle.ray.start, vel);
+		particle.force = Vec2::<Fx32> { x: Fx32::from_int (0), y: Fx32::from_int (0) };
+	}
+	
+	let remaining_dt = step_particle (world, scratch, config, &mut particle.ray, &particle.material, shrink, dt, |_, time, vel, result| {
+		if let Some (e) = ContactEvent::new (handle, result, vel, time) {
+			events.push (e);
+		}
+		
+		if let Some (c) = result.contact () {
+			contact = *result;
+			
+			if is_ground (c.normal, config.gravity) {
+				ground = Some (c.normal);
+			}
+		}
+		stats.record (result);
+	});
+	
+	particle.contact = contact;
+	particle.update_ground (ground, dt);
+	
+	particle.update_sleep (contact != Ray2TraceResult::Miss, config.sleep_speed, config.sleep_ticks, dt);
+	
+	stats.finish_particle (remaining_dt);
+	
+	stats
 }
 
 #[repr(C)]
@@ -21,136 +353,972 @@ pub struct PodVec2 {
 	pub y: i32,
 }
 
-#[no_mangle]
-pub extern fn cher_new (radius: f32, player_start: PodVec2) -> *mut CherenkovSim {
-	let ctx = CherenkovSim {
-		obstacles: vec! [],
-		player: Ray2::new (
-			Vec2 {
-				x: Fx32 {x: player_start.x},
-				y: Fx32 {x: player_start.y},
+// All fields are raw Fx32 bits, same as PodVec2. Restitution and
+// friction are clamped from 0 to 1.
+#[repr(C)]
+pub struct PodMaterial {
+	pub restitution: i32,
+	pub static_friction: i32,
+	pub dynamic_friction: i32,
+	pub min_bounce_speed: i32,
+}
+
+#[repr(C)]
+pub struct PodTraceResult {
+	// 0 for a miss, 1 for a hit, 2 for a pop
+	pub kind: i32,
+	// How far along the ray the hit was, 0 for pops
+	pub t: i32,
+	// Where the particle ends up
+	pub pos: PodVec2,
+	pub normal: PodVec2,
+	// Which obstacle was hit, numbered like cher_set_obstacle_material.
+	// -1 for misses.
+	pub obstacle: i32,
+	// 0 for an arc (a point of the polycapsule), 1 for a line
+	pub feature_kind: i32,
+	// Which point or segment of the obstacle
+	pub feature_index: i32,
+	// How far inside the obstacle it was, 0 for hits
+	pub depth: i32,
+	// Closest point on the obstacle's centerline
+	pub point: PodVec2,
+}
+
+impl PodTraceResult {
+	fn from_result (r: &Ray2TraceResult) -> PodTraceResult {
+		let pod = |v: Vec2 <Fx32>| PodVec2 { x: v.x.x, y: v.y.x };
+		
+		let kind = match *r {
+			Ray2TraceResult::Miss => 0,
+			Ray2TraceResult::Hit (..) => 1,
+			Ray2TraceResult::Pop (..) => 2,
+		};
+		
+		match r.contact () {
+			None => PodTraceResult {
+				kind: kind,
+				t: 0,
+				pos: PodVec2 { x: 0, y: 0 },
+				normal: PodVec2 { x: 0, y: 0 },
+				obstacle: -1,
+				feature_kind: 0,
+				feature_index: 0,
+				depth: 0,
+				point: PodVec2 { x: 0, y: 0 },
 			},
-			Vec2 {
-				x: Fx32::from_q (0, 1),
-				y: Fx32::from_q (1, 1),
+			Some (c) => {
+				let (obstacle, feature_kind, feature_index) = match c.feature {
+					Some (f) => (f.obstacle as i32, match f.kind {
+						FeatureKind::Arc => 0,
+						FeatureKind::Line => 1,
+					}, f.index as i32),
+					None => (-1, 0, 0),
+				};
+				
+				PodTraceResult {
+					kind: kind,
+					t: c.t.x.x,
+					pos: pod (c.pos),
+					normal: pod (c.normal.into ()),
+					obstacle: obstacle,
+					feature_kind: feature_kind,
+					feature_index: feature_index,
+					depth: c.depth.x,
+					point: pod (c.point),
+				}
 			},
-		),
-		radius: Fx32::from_float (radius),
-	};
+		}
+	}
+}
+
+#[repr(C)]
+pub struct PodParticle {
+	pub handle: i32,
+	pub pos: PodVec2,
+	pub vel: PodVec2,
+	// Raw Fx32
+	pub radius: i32,
+	pub flags: u32,
+	// 1 if it's asleep
+	pub sleeping: i32,
+}
+
+impl PodParticle {
+	fn from_particle (p: &Particle) -> PodParticle {
+		let pod = |v: Vec2 <Fx32>| PodVec2 { x: v.x.x, y: v.y.x };
+		
+		PodParticle {
+			handle: p.handle as i32,
+			pos: pod (p.ray.start),
+			vel: pod (p.ray.get_dir ()),
+			radius: p.radius.x,
+			flags: p.flags,
+			sleeping: if p.sleeping { 1 } else { 0 },
+		}
+	}
+}
+
+#[repr(C)]
+pub struct PodPrediction {
+	pub pos: PodVec2,
+	pub vel: PodVec2,
+	// Same as cher_get_player_contact would give after that tick
+	pub contact: PodTraceResult,
+	// 1 if it's fallen asleep, and won't move again
+	pub sleeping: i32,
+}
+
+impl PodPrediction {
+	fn from_particle (p: &Particle) -> PodPrediction {
+		let pod = |v: Vec2 <Fx32>| PodVec2 { x: v.x.x, y: v.y.x };
+		
+		PodPrediction {
+			pos: pod (p.ray.start),
+			vel: pod (p.ray.get_dir ()),
+			contact: PodTraceResult::from_result (&p.contact),
+			sleeping: if p.sleeping { 1 } else { 0 },
+		}
+	}
+}
+
+// Same fields as StepStats, with max_depth as raw Fx32
+#[repr(C)]
+pub struct PodStepStats {
+	pub particles: u32,
+	pub traces: u32,
+	pub hits: u32,
+	pub pops: u32,
+	pub max_subticks: u32,
+	pub slips: u32,
+	pub max_depth: i32,
+}
+
+#[repr(C)]
+pub struct PodContactEvent {
+	pub particle: i32,
+	// Raw Fx32 speed into the surface, before bouncing
+	pub impact_speed: i32,
+	// Raw Fx32 ticks into the step
+	pub time: i32,
+	// kind is 1 for a hit or 2 for a pop
+	pub contact: PodTraceResult,
+}
+
+impl PodContactEvent {
+	fn from_event (e: &ContactEvent) -> PodContactEvent {
+		PodContactEvent {
+			particle: e.particle as i32,
+			impact_speed: e.impact_speed.x,
+			time: e.time.x,
+			contact: PodTraceResult::from_result (&e.result),
+		}
+	}
+}
+
+#[repr(C)]
+pub struct PodRaycastHit {
+	// Raw Fx32 distance from the origin
+	pub distance: i32,
+	// kind is 1 for a hit, or 2 if the origin was inside the obstacle.
+	// t is distance as a fraction of max_distance.
+	pub contact: PodTraceResult,
+}
+
+impl PodRaycastHit {
+	fn from_hit (hit: &RaycastHit) -> PodRaycastHit {
+		let result = if hit.inside {
+			Ray2TraceResult::Pop (hit.contact)
+		}
+		else {
+			Ray2TraceResult::Hit (hit.contact)
+		};
+		
+		PodRaycastHit {
+			distance: hit.distance.x,
+			contact: PodTraceResult::from_result (&result),
+		}
+	}
+}
+
+impl PodMaterial {
+	fn to_material (&self) -> Material {
+		Material {
+			restitution: unit_from_raw (self.restitution).to_small (),
+			static_friction: unit_from_raw (self.static_friction).to_small (),
+			dynamic_friction: unit_from_raw (self.dynamic_friction).to_small (),
+			min_bounce_speed: Fx32 { x: self.min_bounce_speed },
+		}
+	}
+}
+
+#[no_mangle]
+pub extern fn cher_new (radius: f32, player_start: PodVec2) -> *mut CherenkovSim {
+	let world = World::new (Fx32::from_float (radius));
+	let mut ctx = CherenkovSim::new (sync::Arc::new (world));
+	
+	let player = falling_particle (&ctx, player_start);
+	ctx.add_particle (player);
 	
 	unsafe {
 		transmute (Box::new (ctx))
 	}
 }
 
+// Same as cher_new, but shares world's obstacles instead of starting
+// with none. Adding to world afterwards doesn't change this sim, and
+// changing this sim's obstacles doesn't change world or other sims.
 #[no_mangle]
-pub extern fn cher_add_polycapsule (opaque: *mut CherenkovSim, n: i32, points: *const PodVec2) 
-{
-	let context = unsafe { &mut*opaque };
+pub extern fn cher_new_in_world (world: *mut sync::Arc <World>, player_start: PodVec2) -> *mut CherenkovSim {
+	let world = unsafe { &mut*world };
+	
+	// Build it once here, in the shared copy, instead of once per sim
+	if ! world.is_built () {
+		sync::Arc::make_mut (world).build ();
+	}
+	
+	let mut ctx = CherenkovSim::new (world.clone ());
+	
+	let player = falling_particle (&ctx, player_start);
+	ctx.add_particle (player);
+	
+	unsafe {
+		transmute (Box::new (ctx))
+	}
+}
+
+// A level that any number of sims can share. radius is what every
+// obstacle is inflated by, like for cher_new.
+#[no_mangle]
+pub extern fn cher_world_new (radius: f32) -> *mut sync::Arc <World> {
+	let world = sync::Arc::new (World::new (Fx32::from_float (radius)));
+	
+	unsafe {
+		transmute (Box::new (world))
+	}
+}
+
+#[no_mangle]
+pub extern fn cher_world_add_polycapsule (opaque: *mut sync::Arc <World>, n: i32, points: *const PodVec2) {
+	let world = unsafe { &mut*opaque };
+	let points = points_from_pod (n, points);
+	
+	let world = sync::Arc::make_mut (world);
+	let capsule = PolyCapsule::new (&points, world.radius);
+	
+	world.add_obstacle (capsule);
+}
+
+#[no_mangle]
+pub extern fn cher_world_add_one_way_polycapsule (opaque: *mut sync::Arc <World>, n: i32, points: *const PodVec2, up: PodVec2) {
+	let world = unsafe { &mut*opaque };
+	let points = points_from_pod (n, points);
+	
+	let world = sync::Arc::make_mut (world);
+	let up = Vec2 { x: Fx32 { x: up.x }, y: Fx32 { x: up.y } }.normalized ();
+	let capsule = PolyCapsule::new (&points, world.radius).one_way (up);
+	
+	world.add_obstacle (capsule);
+}
+
+// Sims that were made from the world keep it alive until they're
+// deleted too
+#[no_mangle]
+pub extern fn cher_world_delete (opaque: *mut sync::Arc <World>) {
+	let _world: Box <sync::Arc <World>> = unsafe {
+		transmute (opaque)
+	};
+}
+
+fn points_from_pod (n: i32, points: *const PodVec2) -> Vec <Vec2 <Fx32>> {
 	let points = unsafe { slice::from_raw_parts (points, n as usize) };
 	
-	let points: Vec <Vec2 <Fx32>> = points.iter ().map (|pod| {
+	points.iter ().map (|pod| {
 		Vec2 { x: Fx32 { x: pod.x }, y: Fx32 { x: pod.y } }
-	}).collect ();
+	}).collect ()
+}
+
+#[no_mangle]
+pub extern fn cher_add_polycapsule (opaque: *mut CherenkovSim, n: i32, points: *const PodVec2) 
+{
+	let context = unsafe { &mut*opaque };
+	let points = points_from_pod (n, points);
 	
-	let capsule = PolyCapsule::new (&points, context.radius);
+	let capsule = PolyCapsule::new (&points, context.radius ());
 	
-	context.obstacles.push (capsule);
+	context.add_obstacle (capsule);
 }
 
+// Same as cher_add_polycapsule, but particles can pass through from
+// the side opposite 'up'. 'up' doesn't need to be normalized.
 #[no_mangle]
-pub extern fn cher_step (opaque: *mut CherenkovSim) {
+pub extern fn cher_add_one_way_polycapsule (opaque: *mut CherenkovSim, n: i32, points: *const PodVec2, up: PodVec2) 
+{
 	let context = unsafe { &mut*opaque };
+	let points = points_from_pod (n, points);
 	
-	//context.player.start.x = context.player.start.x + Fx32::from_q (1, 1);
+	let up = Vec2 { x: Fx32 { x: up.x }, y: Fx32 { x: up.y } }.normalized ();
+	let capsule = PolyCapsule::new (&points, context.radius ()).one_way (up);
 	
-	step_sim (context);
+	context.add_obstacle (capsule);
 }
 
 #[no_mangle]
-pub extern fn cher_get_player (opaque: *const CherenkovSim) -> PodVec2 {
-	let context = unsafe { &*opaque };
+pub extern fn cher_set_player_material (opaque: *mut CherenkovSim, material: PodMaterial) {
+	let context = unsafe { &mut*opaque };
 	
-	let pos = context.player.start;
+	context.particles [0].material = material.to_material ();
+}
+
+// Obstacles are numbered from 0 in the order they were added
+#[no_mangle]
+pub extern fn cher_set_obstacle_material (opaque: *mut CherenkovSim, obstacle: i32, material: PodMaterial) {
+	let context = unsafe { &mut*opaque };
 	
-	PodVec2 {
-		x: pos.x.x,
-		y: pos.y.x,
+	if let Some (capsule) = context.world_mut ().obstacles.get_mut (obstacle as usize) {
+		*capsule = capsule.with_material (material.to_material ());
+	}
+	else {
+		return;
 	}
+	
+	context.obstacle_changed (obstacle as usize);
 }
 
 #[no_mangle]
-pub extern fn cher_delete (opaque: *mut CherenkovSim) {
-	let _context: Box <CherenkovSim> = unsafe {
-		transmute (opaque)
-	};
+pub extern fn cher_set_segment_material (opaque: *mut CherenkovSim, obstacle: i32, segment: i32, material: PodMaterial) {
+	let context = unsafe { &mut*opaque };
+	
+	if let Some (capsule) = context.world_mut ().obstacles.get_mut (obstacle as usize) {
+		capsule.set_segment_material (segment as usize, material.to_material ());
+	}
+	else {
+		return;
+	}
+	
+	context.obstacle_changed (obstacle as usize);
 }
 
-fn step_sim (ctx: &mut CherenkovSim) {
-	let gravity = Vec2::<Fx32> {
-		x: Fx32::from_q (0, 8),
-		y: Fx32::from_q (1, 8),
-	};
+#[no_mangle]
+pub extern fn cher_step (opaque: *mut CherenkovSim) {
+	let context = unsafe { &mut*opaque };
 	
-	let mut clock = Fx32::from_int (0);
+	//context.player.start.x = context.player.start.x + Fx32::from_q (1, 1);
 	
-	let mut remaining_dt = Fx32::from_int (1);
+	context.step ();
+}
+
+// Same as cher_step, but for dt ticks instead of 1. dt is raw Fx32.
+// Steps longer than a tick are split up, so they cost about as much
+// as calling cher_step that many times.
+#[no_mangle]
+pub extern fn cher_step_dt (opaque: *mut CherenkovSim, dt: i32) {
+	let context = unsafe { &mut*opaque };
 	
-	let mut particle = Ray2::new (ctx.player.start, ctx.player.get_dir () + gravity);
+	context.step_dt (Fx32 { x: dt });
+}
+
+// Traces n rays against the obstacles in one go. Each input array
+// holds n raw Fx32 values, and out must have room for n results.
+#[no_mangle]
+pub extern fn cher_trace_batch (opaque: *mut CherenkovSim, n: i32, start_x: *const i32, start_y: *const i32, dir_x: *const i32, dir_y: *const i32, out: *mut PodTraceResult) {
+	let context = unsafe { &mut*opaque };
+	let n = n as usize;
 	
-	for subtick in 0..4 {
-		let trace_result = ctx.obstacles.iter ().map (|capsule| {
-			let dt_particle = particle.apply_dt (remaining_dt.to_small ());
-			
-			let point_results = capsule.arcs.iter ().map (|obstacle| ray_trace_arc (&dt_particle, obstacle));
-			
-			let line_results = capsule.lines.iter ().map (|line| ray_trace_line_2 (&dt_particle, line)); 
-			
-			point_results.chain (line_results).fold ( Ray2TraceResult::Miss, Ray2TraceResult::fold)
-		}).fold (Ray2TraceResult::Miss, Ray2TraceResult::fold);
-		
-		match trace_result {
-			Ray2TraceResult::Miss => {
-				particle.start = particle.start + (particle.get_dir () * remaining_dt);
-				// Consume the entire remaining tick timestep
-				clock = clock + Fx32::from (remaining_dt);
-				remaining_dt = Fx32::from_int (0);
-			},
-			Ray2TraceResult::Pop (ccd_pos, normal) => {
-				let reflected_dir = particle.get_dir ().reflect_res (Vec2::<Fx32>::from (normal).normalized (), Fx32::from_q (0, 1024).to_small ());
-				
-				let new_dir = reflected_dir;
-				
-				particle.start = ccd_pos;
-				if particle.get_dir () * normal < 0 {
-					particle = Ray2::new (particle.start, new_dir);
-				}
-				
-				// Consume no time - This may lead to time dilation
-				// for some objects if we run short of CPU
-				
-				//println! ("Pop");
-			},
-			Ray2TraceResult::Hit (t, ccd_pos, normal) => {
-				let old_vel = particle.get_dir ();
-				
-				particle = Ray2::new (ccd_pos, particle.get_dir ().reflect_res (Vec2::<Fx32>::from (normal).normalized (), Fx32::from_q (512, 1024).to_small ()));
-				
-				// TODO: only works if dt == 1
-				// Consume just the right portion of time
-				let consumed_time = remaining_dt * Fx32::from (t);
-				remaining_dt = remaining_dt - consumed_time;
-				clock = clock + consumed_time;
-				
-				//println! ("Hit {:?}, {:?}, {:?}", Vec2::<Fx32>::from (normal).normalized (), old_vel, particle.get_dir ());
-			},
-		};
-		
-		if remaining_dt <= Fx32::from_int (0) {
-			break;
-		}
-	}
+	let batch = unsafe {
+		RayBatch::new (
+			slice::from_raw_parts (start_x, n),
+			slice::from_raw_parts (start_y, n),
+			slice::from_raw_parts (dir_x, n),
+			slice::from_raw_parts (dir_y, n),
+		)
+	};
+	let out = unsafe { slice::from_raw_parts_mut (out, n) };
 	
-	ctx.player = particle;
+	let mut results = vec! [];
+	trace_batch (context.broadphase (), &batch, &mut results);
+	
+	for (pod, result) in out.iter_mut ().zip (results.iter ()) {
+		*pod = PodTraceResult::from_result (result);
+	}
 }
 
+// If thin is non-zero, the ray has no width and only hits the
+// obstacles' centerlines. Otherwise it hits them the way a particle's
+// center would.
+fn raycast_shrink (context: &CherenkovSim, thin: i32) -> Fx32 {
+	if thin != 0 {
+		context.radius ()
+	}
+	else {
+		Fx32::from_int (0)
+	}
+}
+
+// Finds the first obstacle along a ray. Returns 1 and fills in out if
+// something was hit, 0 if not. max_distance is raw Fx32.
+#[no_mangle]
+pub extern fn cher_raycast (opaque: *mut CherenkovSim, origin: PodVec2, dir: PodVec2, max_distance: i32, thin: i32, out: *mut PodRaycastHit) -> i32 {
+	let context = unsafe { &mut*opaque };
+	let out = unsafe { &mut*out };
+	
+	let shrink = raycast_shrink (context, thin);
+	let origin = Vec2 { x: Fx32 { x: origin.x }, y: Fx32 { x: origin.y } };
+	let dir = Vec2 { x: Fx32 { x: dir.x }, y: Fx32 { x: dir.y } };
+	
+	match raycast (context.broadphase (), origin, dir, Fx32 { x: max_distance }, shrink) {
+		Some (hit) => {
+			*out = PodRaycastHit::from_hit (&hit);
+			1
+		},
+		None => 0,
+	}
+}
+
+// Same as cher_raycast, but finds every obstacle along the ray, nearest
+// first. Writes up to max_hits hits and returns how many it wrote.
+#[no_mangle]
+pub extern fn cher_raycast_all (opaque: *mut CherenkovSim, origin: PodVec2, dir: PodVec2, max_distance: i32, thin: i32, max_hits: i32, out: *mut PodRaycastHit) -> i32 {
+	let context = unsafe { &mut*opaque };
+	let out = unsafe { slice::from_raw_parts_mut (out, max_hits as usize) };
+	
+	let shrink = raycast_shrink (context, thin);
+	let origin = Vec2 { x: Fx32 { x: origin.x }, y: Fx32 { x: origin.y } };
+	let dir = Vec2 { x: Fx32 { x: dir.x }, y: Fx32 { x: dir.y } };
+	
+	let mut hits = vec! [];
+	raycast_all (context.broadphase (), origin, dir, Fx32 { x: max_distance }, shrink, &mut hits);
+	
+	for (pod, hit) in out.iter_mut ().zip (hits.iter ()) {
+		*pod = PodRaycastHit::from_hit (hit);
+	}
+	
+	cmp::min (hits.len (), out.len ()) as i32
+}
+
+// Sweeps a circle without moving anything. radius is raw Fx32.
+// Returns 0 if it moved the whole way, 1 if it hit something and 2 if
+// it was already overlapping something at the start. out gets the
+// contact, with t as the fraction of motion it managed.
+#[no_mangle]
+pub extern fn cher_sweep_circle (opaque: *mut CherenkovSim, start: PodVec2, motion: PodVec2, radius: i32, out: *mut PodTraceResult) -> i32 {
+	let context = unsafe { &mut*opaque };
+	let out = unsafe { &mut*out };
+	
+	let start = Vec2 { x: Fx32 { x: start.x }, y: Fx32 { x: start.y } };
+	let motion = Vec2 { x: Fx32 { x: motion.x }, y: Fx32 { x: motion.y } };
+	
+	let result = match context.sweep_circle (start, motion, Fx32 { x: radius }) {
+		Sweep::Clear => Ray2TraceResult::Miss,
+		Sweep::Hit (c) => Ray2TraceResult::Hit (c),
+		Sweep::Overlap (c) => Ray2TraceResult::Pop (c),
+	};
+	
+	*out = PodTraceResult::from_result (&result);
+	out.kind
+}
+
+#[no_mangle]
+pub extern fn cher_set_gravity (opaque: *mut CherenkovSim, gravity: PodVec2) {
+	let context = unsafe { &mut*opaque };
+	
+	context.set_gravity (Vec2 { x: Fx32 { x: gravity.x }, y: Fx32 { x: gravity.y } });
+}
+
+#[no_mangle]
+pub extern fn cher_get_gravity (opaque: *const CherenkovSim) -> PodVec2 {
+	let context = unsafe { &*opaque };
+	let gravity = context.config.gravity;
+	
+	PodVec2 { x: gravity.x.x, y: gravity.y.x }
+}
+
+// How many traces each particle may use per tick, at least 1
+#[no_mangle]
+pub extern fn cher_set_subticks (opaque: *mut CherenkovSim, subticks: i32) {
+	let context = unsafe { &mut*opaque };
+	
+	context.config.subticks = cmp::max (subticks, 1) as u32;
+}
+
+#[no_mangle]
+pub extern fn cher_get_subticks (opaque: *const CherenkovSim) -> i32 {
+	let context = unsafe { &*opaque };
+	
+	context.config.subticks as i32
+}
+
+// Restitutions and frictions are raw Fx32, clamped from 0 to 1
+fn unit_from_raw (x: i32) -> Fx32 {
+	Fx32 { x: cmp::max (0, cmp::min (x, Fx32::from_int (1).x)) }
+}
+
+// Multiplied into every hit's restitution, on top of the materials.
+// Starts out at 1.
+#[no_mangle]
+pub extern fn cher_set_restitution (opaque: *mut CherenkovSim, restitution: i32) {
+	let context = unsafe { &mut*opaque };
+	
+	context.config.restitution = unit_from_raw (restitution).to_small ();
+}
+
+#[no_mangle]
+pub extern fn cher_get_restitution (opaque: *const CherenkovSim) -> i32 {
+	let context = unsafe { &*opaque };
+	
+	context.config.restitution.x.x
+}
+
+// How much particles bounce when they're popped out of an obstacle.
+// Starts out at 0.
+#[no_mangle]
+pub extern fn cher_set_pop_restitution (opaque: *mut CherenkovSim, restitution: i32) {
+	let context = unsafe { &mut*opaque };
+	
+	context.config.pop_restitution = unit_from_raw (restitution).to_small ();
+}
+
+#[no_mangle]
+pub extern fn cher_get_pop_restitution (opaque: *const CherenkovSim) -> i32 {
+	let context = unsafe { &*opaque };
+	
+	context.config.pop_restitution.x.x
+}
+
+// Raw Fx32 units per tick. 0 or less means no cap, which is how it
+// starts out.
+#[no_mangle]
+pub extern fn cher_set_max_speed (opaque: *mut CherenkovSim, max_speed: i32) {
+	let context = unsafe { &mut*opaque };
+	
+	context.config.max_speed = if max_speed > 0 {
+		Some (Fx32 { x: max_speed })
+	}
+	else {
+		None
+	};
+}
+
+#[no_mangle]
+pub extern fn cher_get_max_speed (opaque: *const CherenkovSim) -> i32 {
+	let context = unsafe { &*opaque };
+	
+	context.config.max_speed.map (|s| s.x).unwrap_or (0)
+}
+
+// Velocities and impulses are raw Fx32 units per tick. Waking the
+// particle up is part of all of these.
+#[no_mangle]
+pub extern fn cher_apply_impulse (opaque: *mut CherenkovSim, handle: i32, impulse: PodVec2) {
+	let context = unsafe { &mut*opaque };
+	
+	if let Some (p) = context.particle_mut (handle as u32) {
+		p.apply_impulse (Vec2 { x: Fx32 { x: impulse.x }, y: Fx32 { x: impulse.y } });
+	}
+}
+
+// Pushes during the next step only, like gravity does, for however
+// long cher_step_dt's dt is. Call it every step to keep pushing.
+#[no_mangle]
+pub extern fn cher_apply_force (opaque: *mut CherenkovSim, handle: i32, force: PodVec2) {
+	let context = unsafe { &mut*opaque };
+	
+	if let Some (p) = context.particle_mut (handle as u32) {
+		p.apply_force (Vec2 { x: Fx32 { x: force.x }, y: Fx32 { x: force.y } });
+	}
+}
+
+#[no_mangle]
+pub extern fn cher_set_velocity (opaque: *mut CherenkovSim, handle: i32, vel: PodVec2) {
+	let context = unsafe { &mut*opaque };
+	
+	if let Some (p) = context.particle_mut (handle as u32) {
+		p.set_velocity (Vec2 { x: Fx32 { x: vel.x }, y: Fx32 { x: vel.y } });
+	}
+}
+
+// Jumps off the ground at speed, if the particle touched the ground
+// no more than coyote_ticks ticks ago. speed is raw Fx32. Returns 1 if
+// it jumped and 0 if it couldn't.
+#[no_mangle]
+pub extern fn cher_jump (opaque: *mut CherenkovSim, handle: i32, speed: i32, coyote_ticks: i32) -> i32 {
+	let context = unsafe { &mut*opaque };
+	
+	let coyote_time = Fx32::from_int (cmp::max (0, cmp::min (coyote_ticks, MAX_AIR_TIME)));
+	
+	match context.particle_mut (handle as u32) {
+		Some (p) => if p.jump (Fx32 { x: speed }, coyote_time) { 1 } else { 0 },
+		None => 0,
+	}
+}
+
+// Returns 1 if the player is asleep, 0 if not
+#[no_mangle]
+pub extern fn cher_is_sleeping (opaque: *const CherenkovSim) -> i32 {
+	let context = unsafe { &*opaque };
+	
+	if context.particles [0].sleeping { 1 } else { 0 }
+}
+
+#[no_mangle]
+pub extern fn cher_wake (opaque: *mut CherenkovSim) {
+	let context = unsafe { &mut*opaque };
+	
+	context.particles [0].wake ();
+}
+
+#[no_mangle]
+pub extern fn cher_get_player (opaque: *const CherenkovSim) -> PodVec2 {
+	cher_get_particle (opaque, 0)
+}
+
+// The sim's own radius, starting out falling like the player always has
+fn falling_particle (context: &CherenkovSim, start: PodVec2) -> Particle {
+	Particle::new (
+		Vec2 {
+			x: Fx32 {x: start.x},
+			y: Fx32 {x: start.y},
+		},
+		Vec2 {
+			x: Fx32::from_q (0, 1),
+			y: Fx32::from_q (1, 1),
+		},
+		context.radius (),
+	)
+}
+
+// Adds another particle that starts out falling like the player.
+// Returns its handle.
+#[no_mangle]
+pub extern fn cher_add_particle (opaque: *mut CherenkovSim, start: PodVec2) -> i32 {
+	let context = unsafe { &mut*opaque };
+	
+	let particle = falling_particle (context, start);
+	
+	context.add_particle (particle) as i32
+}
+
+// Same as cher_add_particle, but with its own velocity, radius and
+// flags. vel and radius are raw Fx32.
+#[no_mangle]
+pub extern fn cher_spawn_particle (opaque: *mut CherenkovSim, start: PodVec2, vel: PodVec2, radius: i32, flags: u32) -> i32 {
+	let context = unsafe { &mut*opaque };
+	
+	let mut particle = falling_particle (context, start);
+	particle.ray = Ray2::new (particle.ray.start, Vec2 { x: Fx32 { x: vel.x }, y: Fx32 { x: vel.y } });
+	particle.radius = Fx32 { x: radius };
+	particle.flags = flags;
+	
+	context.add_particle (particle) as i32
+}
+
+// Returns 1 if it was removed, 0 if there's no such particle. The
+// player, handle 0, can't be removed. Handles are never reused.
+#[no_mangle]
+pub extern fn cher_remove_particle (opaque: *mut CherenkovSim, handle: i32) -> i32 {
+	let context = unsafe { &mut*opaque };
+	
+	if context.remove_particle (handle as u32) { 1 } else { 0 }
+}
+
+#[no_mangle]
+pub extern fn cher_particle_count (opaque: *const CherenkovSim) -> i32 {
+	let context = unsafe { &*opaque };
+	
+	context.particles.len () as i32
+}
+
+// Writes up to max handles, in the order the particles are stepped,
+// and returns how many it wrote
+#[no_mangle]
+pub extern fn cher_get_particle_handles (opaque: *const CherenkovSim, max: i32, out: *mut i32) -> i32 {
+	let context = unsafe { &*opaque };
+	let out = unsafe { slice::from_raw_parts_mut (out, cmp::max (max, 0) as usize) };
+	
+	for (handle, particle) in out.iter_mut ().zip (context.particles.iter ()) {
+		*handle = particle.handle as i32;
+	}
+	
+	cmp::min (out.len (), context.particles.len ()) as i32
+}
+
+// (0, 0) if there's no such particle
+#[no_mangle]
+pub extern fn cher_get_particle (opaque: *const CherenkovSim, handle: i32) -> PodVec2 {
+	let context = unsafe { &*opaque };
+	
+	match context.particle (handle as u32) {
+		Some (p) => PodVec2 {
+			x: p.ray.start.x.x,
+			y: p.ray.start.y.x,
+		},
+		None => PodVec2 { x: 0, y: 0 },
+	}
+}
+
+// Returns 1 and fills in out, or 0 if there's no such particle
+#[no_mangle]
+pub extern fn cher_get_particle_state (opaque: *const CherenkovSim, handle: i32, out: *mut PodParticle) -> i32 {
+	let context = unsafe { &*opaque };
+	let out = unsafe { &mut*out };
+	
+	match context.particle (handle as u32) {
+		Some (p) => {
+			*out = PodParticle::from_particle (p);
+			1
+		},
+		None => 0,
+	}
+}
+
+// flags is PARTICLE_FROZEN (1), plus any bits of the game's own.
+// Wakes the particle up.
+#[no_mangle]
+pub extern fn cher_set_particle_flags (opaque: *mut CherenkovSim, handle: i32, flags: u32) {
+	let context = unsafe { &mut*opaque };
+	
+	if let Some (p) = context.particle_mut (handle as u32) {
+		p.flags = flags;
+		p.wake ();
+	}
+}
+
+// radius is raw Fx32. Wakes the particle up, since it might not fit
+// where it's resting any more.
+#[no_mangle]
+pub extern fn cher_set_particle_radius (opaque: *mut CherenkovSim, handle: i32, radius: i32) {
+	let context = unsafe { &mut*opaque };
+	
+	if let Some (p) = context.particle_mut (handle as u32) {
+		p.radius = Fx32 { x: radius };
+		p.wake ();
+	}
+}
+
+// The last thing the player touched during the last step, with kind 0
+// if it didn't touch anything. Sleeping particles keep the contact
+// they fell asleep on.
+#[no_mangle]
+pub extern fn cher_get_player_contact (opaque: *const CherenkovSim) -> PodTraceResult {
+	let context = unsafe { &*opaque };
+	
+	PodTraceResult::from_result (&context.particles [0].contact)
+}
+
+fn write_prediction (context: &CherenkovSim, particle: &Particle, ticks: i32, out: *mut PodPrediction) -> i32 {
+	let ticks = cmp::max (ticks, 0);
+	let out = unsafe { slice::from_raw_parts_mut (out, ticks as usize) };
+	
+	let mut predicted = vec! [];
+	context.predict (particle, ticks as u32, &mut predicted);
+	
+	for (pod, p) in out.iter_mut ().zip (predicted.iter ()) {
+		*pod = PodPrediction::from_particle (p);
+	}
+	
+	ticks
+}
+
+// Where a particle will be after each of the next few ticks, without
+// stepping the sim. out must have room for ticks predictions. Returns
+// how many it wrote, which is 0 if there's no such particle.
+#[no_mangle]
+pub extern fn cher_predict (opaque: *const CherenkovSim, handle: i32, ticks: i32, out: *mut PodPrediction) -> i32 {
+	let context = unsafe { &*opaque };
+	
+	let particle = match context.particle (handle as u32) {
+		Some (p) => *p,
+		None => return 0,
+	};
+	
+	write_prediction (context, &particle, ticks, out)
+}
+
+// Same as cher_predict, but for the particle thrown from pos with vel,
+// e.g. for aiming arcs. It's woken up, but keeps its material, radius
+// and flags.
+#[no_mangle]
+pub extern fn cher_predict_launch (opaque: *const CherenkovSim, handle: i32, pos: PodVec2, vel: PodVec2, ticks: i32, out: *mut PodPrediction) -> i32 {
+	let context = unsafe { &*opaque };
+	
+	let mut particle = match context.particle (handle as u32) {
+		Some (p) => *p,
+		None => return 0,
+	};
+	particle.ray = Ray2::new (
+		Vec2 { x: Fx32 { x: pos.x }, y: Fx32 { x: pos.y } },
+		Vec2 { x: Fx32 { x: vel.x }, y: Fx32 { x: vel.y } },
+	);
+	particle.contact = Ray2TraceResult::Miss;
+	particle.wake ();
+	
+	write_prediction (context, &particle, ticks, out)
+}
+
+// Counters added up over every step since the last cher_reset_stats
+#[no_mangle]
+pub extern fn cher_get_stats (opaque: *const CherenkovSim) -> PodStepStats {
+	let context = unsafe { &*opaque };
+	let s = &context.stats;
+	
+	PodStepStats {
+		particles: s.particles,
+		traces: s.traces,
+		hits: s.hits,
+		pops: s.pops,
+		max_subticks: s.max_subticks,
+		slips: s.slips,
+		max_depth: s.max_depth.x,
+	}
+}
+
+#[no_mangle]
+pub extern fn cher_reset_stats (opaque: *mut CherenkovSim) {
+	let context = unsafe { &mut*opaque };
+	
+	context.reset_stats ();
+}
+
+// Moves up to max of the oldest contact events into out, and returns
+// how many it wrote. Call it every frame or two so the queue doesn't
+// fill up.
+#[no_mangle]
+pub extern fn cher_drain_events (opaque: *mut CherenkovSim, max: i32, out: *mut PodContactEvent) -> i32 {
+	let context = unsafe { &mut*opaque };
+	
+	if max <= 0 {
+		return 0;
+	}
+	
+	let mut events = vec! [];
+	context.events.drain (max as usize, &mut events);
+	
+	let out = unsafe { slice::from_raw_parts_mut (out, events.len ()) };
+	for (o, e) in out.iter_mut ().zip (events.iter ()) {
+		*o = PodContactEvent::from_event (e);
+	}
+	
+	events.len () as i32
+}
+
+// How many events were thrown away because the queue was full, since
+// the last call
+#[no_mangle]
+pub extern fn cher_take_dropped_events (opaque: *mut CherenkovSim) -> i32 {
+	let context = unsafe { &mut*opaque };
+	
+	cmp::min (context.events.take_dropped (), i32::max_value () as u32) as i32
+}
+
+// Doesn't throw away events already queued past the new capacity
+#[no_mangle]
+pub extern fn cher_set_event_capacity (opaque: *mut CherenkovSim, capacity: i32) {
+	let context = unsafe { &mut*opaque };
+	
+	context.events.capacity = cmp::max (capacity, 0) as usize;
+}
+
+// Checksum of every particle and the config, for lockstep games to
+// compare after each step. If obstacles isn't 0, the obstacles are
+// hashed too. Same bits in, same hash out, on any platform.
+#[no_mangle]
+pub extern fn cher_state_hash (opaque: *const CherenkovSim, obstacles: i32) -> u64 {
+	let context = unsafe { &*opaque };
+	
+	state_hash (context, obstacles != 0)
+}
+
+// How many bytes cher_snapshot_write needs right now
+#[no_mangle]
+pub extern fn cher_snapshot_size (opaque: *const CherenkovSim) -> i32 {
+	let context = unsafe { &*opaque };
+	
+	let mut data = vec! [];
+	write_snapshot (&mut data, context).unwrap ();
+	
+	data.len () as i32
+}
+
+// Saves everything needed to carry on from here later. Returns how
+// many bytes it wrote, or 0 if n is too small.
+#[no_mangle]
+pub extern fn cher_snapshot_write (opaque: *const CherenkovSim, n: i32, out: *mut u8) -> i32 {
+	let context = unsafe { &*opaque };
+	
+	let mut data = vec! [];
+	write_snapshot (&mut data, context).unwrap ();
+	
+	if (n as i64) < data.len () as i64 {
+		return 0;
+	}
+	
+	let out = unsafe { slice::from_raw_parts_mut (out, data.len ()) };
+	out.copy_from_slice (&data);
+	
+	data.len () as i32
+}
+
+// Puts the sim back how it was when the snapshot was written, bit for
+// bit. Stats and queued events are cleared. Returns 0 and leaves the
+// sim alone if the data isn't a snapshot this version can read.
+// If the obstacles haven't changed since, the sim keeps sharing its
+// world with any other sims.
+#[no_mangle]
+pub extern fn cher_snapshot_read (opaque: *mut CherenkovSim, n: i32, data: *const u8) -> i32 {
+	let context = unsafe { &mut*opaque };
+	
+	if n < 0 {
+		return 0;
+	}
+	
+	let data = unsafe { slice::from_raw_parts (data, n as usize) };
+	
+	let result = match read_snapshot_in_world (&mut &data [..], context.world.clone ()) {
+		Err (SnapshotError::DifferentWorld) => read_snapshot (&mut &data [..]),
+		result => result,
+	};
+	
+	restore (context, result)
+}
+
+// Same as cher_snapshot_read, but the sim ends up sharing world, like
+// one from cher_new_in_world. Returns 0 and leaves the sim alone if
+// the snapshot's obstacles aren't the same as world's.
+#[no_mangle]
+pub extern fn cher_snapshot_read_in_world (opaque: *mut CherenkovSim, world: *mut sync::Arc <World>, n: i32, data: *const u8) -> i32 {
+	let context = unsafe { &mut*opaque };
+	let world = unsafe { &mut*world };
+	
+	if n < 0 {
+		return 0;
+	}
+	
+	let mut data = unsafe { slice::from_raw_parts (data, n as usize) };
+	
+	if ! world.is_built () {
+		sync::Arc::make_mut (world).build ();
+	}
+	
+	let result = read_snapshot_in_world (&mut data, world.clone ());
+	restore (context, result)
+}
+
+fn restore (context: &mut CherenkovSim, result: Result <CherenkovSim, SnapshotError>) -> i32 {
+	match result {
+		Ok (sim) => {
+			let capacity = context.events.capacity;
+			*context = sim;
+			context.events.capacity = capacity;
+			
+			1
+		},
+		Err (_) => 0,
+	}
+}
+
+#[no_mangle]
+pub extern fn cher_delete (opaque: *mut CherenkovSim) {
+	let _context: Box <CherenkovSim> = unsafe {
+		transmute (opaque)
+	};
+}
@@ -0,0 +1,49 @@
+use fx32::Fx32;
+use fx32::Fx32Small;
+use vec2::Vec2;
+
+// Knobs for step_particle
+#[derive (Clone, Copy, Debug)]
+pub struct PhysicsConfig {
+	// Added to every particle's velocity once per tick
+	pub gravity: Vec2 <Fx32>,
+	// How many traces a particle may use per tick before giving up
+	// on the rest of the tick
+	pub subticks: u32,
+	// Hits slower than this along the normal never bounce, whatever
+	// the materials say. Keeps resting particles from jittering.
+	// Before this existed, slow hits bounced like any other, so set it
+	// to 0 to get that back.
+	pub contact_speed: Fx32,
+	// A particle that stays slower than sleep_speed for sleep_ticks
+	// ticks in a row falls asleep
+	pub sleep_speed: Fx32,
+	pub sleep_ticks: u32,
+	// Multiplied into every hit's restitution, on top of the materials.
+	// 1 leaves it up to them.
+	pub restitution: Fx32Small,
+	// Used instead of the materials' restitution when a particle is
+	// popped out of an obstacle. 0 means it comes out sliding.
+	pub pop_restitution: Fx32Small,
+	// Velocities are clamped to this long at the start of every tick,
+	// after gravity. None lets them go as fast as they like.
+	pub max_speed: Option <Fx32>,
+}
+
+impl Default for PhysicsConfig {
+	fn default () -> PhysicsConfig {
+		PhysicsConfig {
+			gravity: Vec2::<Fx32> {
+				x: Fx32::from_q (0, 8),
+				y: Fx32::from_q (1, 8),
+			},
+			subticks: 4,
+			contact_speed: Fx32::from_q (1, 4),
+			sleep_speed: Fx32::from_q (1, 4),
+			sleep_ticks: 30,
+			restitution: Fx32::from_int (1).to_small (),
+			pop_restitution: Fx32::from_int (0).to_small (),
+			max_speed: None,
+		}
+	}
+}
@@ -0,0 +1,69 @@
+use bake::FeatureRef;
+use fx32::Fx32;
+use fx32::Fx32Small;
+use material::Material;
+use vec2::Vec2;
+
+/*
+Everything we know about one collision.
+
+The tracers fill in the geometry, and Feature::trace fills in which
+obstacle it came from. Shapes traced on their own, outside a baked
+world, leave feature as None.
+*/
+#[derive (Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Contact {
+	pub feature: Option <FeatureRef>,
+	// How far along the ray the hit was. 0 for pops.
+	pub t: Fx32Small,
+	// How far inside the obstacle the ray started. 0 for hits.
+	pub depth: Fx32,
+	// Where the particle ends up, on the obstacle's surface
+	pub pos: Vec2 <Fx32>,
+	// The closest point on the obstacle's centerline, so pos minus
+	// the normal times the obstacle's radius
+	pub point: Vec2 <Fx32>,
+	// Points out of the obstacle towards the particle
+	pub normal: Vec2 <Fx32Small>,
+	pub material: Material,
+}
+
+impl Contact {
+	pub fn hit (t: Fx32Small, pos: Vec2 <Fx32>, point: Vec2 <Fx32>, normal: Vec2 <Fx32Small>, material: Material) -> Contact {
+		Contact {
+			feature: None,
+			t: t,
+			depth: Fx32::from_int (0),
+			pos: pos,
+			point: point,
+			normal: normal,
+			material: material,
+		}
+	}
+	
+	pub fn pop (depth: Fx32, pos: Vec2 <Fx32>, point: Vec2 <Fx32>, normal: Vec2 <Fx32Small>, material: Material) -> Contact {
+		Contact {
+			feature: None,
+			t: Fx32::from_int (0).to_small (),
+			depth: depth,
+			pos: pos,
+			point: point,
+			normal: normal,
+			material: material,
+		}
+	}
+	
+	// Breaks ties between contacts using only what they look like, not
+	// which obstacle they came from, so the order obstacles were added
+	// in doesn't matter
+	pub fn tie_key (&self) -> (i32, i32, i32, i32, i32, i32, i32, i32, i32, i32) {
+		let m = &self.material;
+		
+		(
+			self.pos.x.x, self.pos.y.x,
+			self.normal.x.x.x, self.normal.y.x.x,
+			self.point.x.x, self.point.y.x,
+			m.restitution.x.x, m.static_friction.x.x, m.dynamic_friction.x.x, m.min_bounce_speed.x,
+		)
+	}
+}
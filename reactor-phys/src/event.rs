@@ -0,0 +1,83 @@
+use fx32::Fx32;
+use raytraceresult::Ray2TraceResult;
+use vec2::Vec2;
+
+/*
+Contacts from each step, kept for the game to drain for impact
+sounds, sparks and the like.
+
+The queue only holds so many. Once it's full, newer events are
+counted and thrown away, so a game that forgets to drain it doesn't
+leak memory.
+*/
+
+#[derive (Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ContactEvent {
+	// Handle of the particle that touched something
+	pub particle: u32,
+	// Hit or Pop, never Miss. The contact says which obstacle, where,
+	// and which way the normal points.
+	pub result: Ray2TraceResult,
+	// How fast the particle was going into the surface, before it
+	// bounced or slid
+	pub impact_speed: Fx32,
+	// How far into the step it happened, in ticks
+	pub time: Fx32,
+}
+
+impl ContactEvent {
+	// None for misses
+	pub fn new (particle: u32, result: &Ray2TraceResult, vel: Vec2 <Fx32>, time: Fx32) -> Option <ContactEvent> {
+		let c = match result.contact () {
+			Some (c) => c,
+			None => return None,
+		};
+		let into = -vel.dot_64 (c.normal.into ());
+		
+		Some (ContactEvent {
+			particle: particle,
+			result: *result,
+			impact_speed: if into > 0 { into } else { Fx32::from_int (0) },
+			time: time,
+		})
+	}
+}
+
+pub struct EventQueue {
+	pub events: Vec <ContactEvent>,
+	pub capacity: usize,
+	// Events thrown away since the last take_dropped
+	pub dropped: u32,
+}
+
+impl EventQueue {
+	pub fn new (capacity: usize) -> EventQueue {
+		EventQueue {
+			events: vec! [],
+			capacity: capacity,
+			dropped: 0,
+		}
+	}
+	
+	pub fn push (&mut self, event: ContactEvent) {
+		if self.events.len () < self.capacity {
+			self.events.push (event);
+		}
+		else {
+			self.dropped = self.dropped.saturating_add (1);
+		}
+	}
+	
+	// Moves up to max of the oldest events into out
+	pub fn drain (&mut self, max: usize, out: &mut Vec <ContactEvent>) {
+		let n = if max < self.events.len () { max } else { self.events.len () };
+		
+		out.extend (self.events.drain (..n));
+	}
+	
+	pub fn take_dropped (&mut self) -> u32 {
+		let dropped = self.dropped;
+		self.dropped = 0;
+		dropped
+	}
+}
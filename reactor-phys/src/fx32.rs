@@ -154,6 +154,17 @@ impl Fx32 {
 	pub fn sqrt (self) -> Fx32 {
 		Fx32 { x: self.x.sqrt () << HALF_FRACTIONAL_BITS }
 	}
+	
+	// Length of the vector (x, y). The squares are summed in 64 bits,
+	// so nothing is lost to prescaling.
+	pub fn hypot_64 (x: Fx32, y: Fx32) -> Fx32 {
+		let x = x.x as DoubleInt;
+		let y = y.x as DoubleInt;
+		
+		Fx32 {
+			x: (x * x + y * y).sqrt () as Int,
+		}
+	}
 }
 /*
 impl fmt::Debug for Fx32 {
@@ -1,26 +1,1742 @@
+extern crate byteorder;
+#[cfg (feature = "parallel")]
+extern crate rayon;
+
+pub mod aabb;
 pub mod arc;
+pub mod bake;
 pub mod basis;
+pub mod batch;
+pub mod broadphase;
 pub mod cherenkov;
 pub mod circle;
+pub mod config;
+pub mod contact;
+pub mod event;
 pub mod fx32;
+pub mod material;
+pub mod particle;
 pub mod polycapsule;
+pub mod query;
 pub mod ray2;
 pub mod raytrace;
 pub mod raytraceresult;
+pub mod scenario;
+pub mod snapshot;
+pub mod solver;
+pub mod stats;
+pub mod statehash;
+pub mod svg;
 pub mod vec2;
 pub mod wide_line;
+pub mod world;
 
 #[cfg(test)]
 mod tests {
+	use super::aabb::Aabb;
+	use super::bake::FeatureKind;
+	use super::bake::FeatureRef;
+	use super::bake;
+	use super::bake::Shape;
+	use super::batch;
+	use super::batch::RayBatch;
+	use super::broadphase::Broadphase;
+	use super::cherenkov::*;
 	use super::circle::Circle;
+	use super::config::PhysicsConfig;
+	use super::contact::Contact;
 	use super::fx32::Fx32;
+	use super::material::Material;
+	use super::particle::PARTICLE_FROZEN;
+	use super::particle::Particle;
+	use super::polycapsule::PolyCapsule;
+	use super::query;
+	use super::scenario::Scenario;
+	use super::scenario::ScenarioError;
+	use super::snapshot;
+	use super::snapshot::SnapshotError;
+	use super::statehash;
 	use super::ray2::Ray2;
 	use super::raytrace;
+	use super::raytraceresult::Ray2TraceResult;
+	use super::raytraceresult::Resolver;
+	use super::solver;
+	use super::stats::StepStats;
+	use super::svg;
 	use super::vec2::Vec2;
+	use super::wide_line::WideLine;
+	use super::world::World;
+	
+	use byteorder::ByteOrder;
+	use byteorder::LittleEndian;
+	
+	use std::cmp;
+	use std::sync;
+	
+	// Deterministic junk for tests that want lots of cases
+	fn random (state: &mut u32, min: i32, max: i32) -> i32 {
+		*state = state.wrapping_mul (1664525).wrapping_add (1013904223);
+		min + ((*state >> 8) % (max - min) as u32) as i32
+	}
+	
+	// A big level of random zig-zags, like the ones in polylines.lua
+	fn random_level (seed: u32, count: i32) -> Vec <PolyCapsule> {
+		let mut state = seed;
+		
+		(0..count).map (|_| {
+			let mut p = Vec2 {x: Fx32::from_int (random (&mut state, 0, 2000)), y: Fx32::from_int (random (&mut state, 0, 2000))};
+			let n = random (&mut state, 2, 8);
+			
+			let points: Vec <Vec2 <Fx32>> = (0..n).map (|_| {
+				p = p + Vec2 {x: Fx32::from_int (random (&mut state, -40, 40)), y: Fx32::from_int (random (&mut state, -40, 40))};
+				p
+			}).collect ();
+			
+			PolyCapsule::new (&points, Fx32::from_int (8))
+		}).collect ()
+	}
+	
+	// Rays that start outside every obstacle
+	fn random_rays (seed: u32, count: i32, level: &[PolyCapsule]) -> Vec <Ray2> {
+		let mut state = seed;
+		
+		(0..count).map (|_| {
+			Ray2::new (
+				Vec2 {x: Fx32::from_int (random (&mut state, 0, 2000)), y: Fx32::from_int (random (&mut state, 0, 2000))},
+				Vec2 {x: Fx32::from_q (random (&mut state, -800, 800), 16), y: Fx32::from_q (random (&mut state, -800, 800), 16)},
+			)
+		}).filter (|ray| level.iter ().all (|capsule| {
+			capsule.arcs.iter ().all (|arc| (ray.start - arc.circle.center).length_64 () > arc.circle.radius)
+		})).collect ()
+	}
+	
+	// True if segment a0-a1 properly crosses segment b0-b1. Exact, since
+	// it's all done on raw bits in 64 bits.
+	fn crosses (a0: Vec2 <Fx32>, a1: Vec2 <Fx32>, b0: Vec2 <Fx32>, b1: Vec2 <Fx32>) -> bool {
+		let orient = |p: Vec2 <Fx32>, q: Vec2 <Fx32>, r: Vec2 <Fx32>| {
+			let (qx, qy) = ((q.x.x - p.x.x) as i64, (q.y.x - p.y.x) as i64);
+			let (rx, ry) = ((r.x.x - p.x.x) as i64, (r.y.x - p.y.x) as i64);
+			(qx * ry - qy * rx).signum ()
+		};
+		
+		orient (b0, b1, a0) * orient (b0, b1, a1) < 0 && orient (a0, a1, b0) * orient (a0, a1, b1) < 0
+	}
+	
+	// Throws particles at a random level with all kinds of velocities,
+	// and checks that no move ever crosses the middle of an obstacle
+	#[test]
+	fn no_tunneling () {
+		let level = random_level (10, 300);
+		let world = Broadphase::new (&level);
+		let material = Material::default ();
+		let zero = Vec2 {x: Fx32::from_int (0), y: Fx32::from_int (0)};
+		
+		let mut state = 11;
+		let mut scratch = vec! [];
+		let mut num_particles = 0;
+		let mut num_hits = 0;
+		
+		while num_particles < 3000 {
+			let start = Vec2 {x: Fx32::from_int (random (&mut state, 0, 2000)), y: Fx32::from_int (random (&mut state, 0, 2000))};
+			
+			if query::sweep (&world, start, zero, Fx32::from_int (0)) != query::Sweep::Clear {
+				continue;
+			}
+			num_particles += 1;
+			
+			let vel = match random (&mut state, 0, 5) {
+				// Fast
+				0 => Vec2 {x: Fx32::from_int (random (&mut state, -170, 170)), y: Fx32::from_int (random (&mut state, -170, 170))},
+				// Slower than the tracer's rounding
+				1 => Vec2 {x: Fx32 {x: random (&mut state, -255, 256)}, y: Fx32 {x: random (&mut state, -255, 256)}},
+				// Straight along the axes, parallel to some walls
+				2 => Vec2 {x: Fx32::from_int (random (&mut state, -40, 40)), y: zero.y},
+				3 => Vec2 {x: zero.x, y: Fx32::from_int (random (&mut state, -40, 40))},
+				_ => Vec2 {x: Fx32::from_q (random (&mut state, -800, 800), 64), y: Fx32::from_q (random (&mut state, -800, 800), 64)},
+			};
+			
+			let config = PhysicsConfig {
+				gravity: Vec2 {x: Fx32::from_q (random (&mut state, -16, 16), 64), y: Fx32::from_q (random (&mut state, -16, 16), 64)},
+				.. PhysicsConfig::default ()
+			};
+			
+			let mut particle = Ray2::new (start, vel);
+			
+			for _ in 0..100 {
+				// Gone, and about to leave Fx32's range
+				if particle.start.x.abs () > 8000 || particle.start.y.abs () > 8000 {
+					break;
+				}
+				
+				let mut last = particle.start;
+				
+				solver::step_particle (&world, &mut vec! [], &config, &mut particle, &material, Fx32::from_int (0), Fx32::from_int (1), |p, _, _, result| {
+					if *result != Ray2TraceResult::Miss {
+						num_hits += 1;
+					}
+					
+					world.candidates (&Aabb::from_points (last, p.start).expand (Fx32::from_int (1)), &mut scratch);
+					
+					for i in scratch.iter () {
+						if let Shape::Line (ref line) = world.features [*i as usize].shape {
+							assert! (! crosses (last, p.start, line.start, line.end), "Tunneled from {:?} to {:?} through {:?}", last, p.start, line);
+						}
+					}
+					
+					last = p.start;
+				});
+			}
+		}
+		
+		assert! (num_hits > 1000, "Enough collisions to mean anything");
+	}
+	
+	#[test]
+	fn bake () {
+		let v = |x, y| Vec2 {x: Fx32::from_float (x), y: Fx32::from_float (y)};
+		
+		// Long and diagonal, where scaling down by 256 before
+		// normalizing in 32 bits, like lines used to, loses bits
+		let (start, end) = (v (-1500.25, 700.5), v (1700.75, -611.125));
+		let capsule = PolyCapsule::new (&[start, end], Fx32::from_int (8));
+		let features = bake::bake (&[capsule.clone ()]);
+		
+		let dx = (end.x - start.x).to_f64 ();
+		let dy = (end.y - start.y).to_f64 ();
+		let exact_length = dx.hypot (dy);
+		let raw = |x: f64| (x * 65536.0).round () as i64;
+		let error = |x: Fx32, exact: f64| (x.x as i64 - raw (exact)).abs ();
+		
+		let line = match features [2].shape {
+			Shape::Line (line) => line,
+			_ => panic! ("Arcs come first"),
+		};
+		assert! (error (line.length, exact_length) <= 1, "Length is within a bit");
+		assert! (error (line.tangent.x.x, dx / exact_length) <= 1, "Tangent is within a bit");
+		assert! (error (line.tangent.y.x, dy / exact_length) <= 1);
+		assert_eq! (line.normal.x.x, -line.tangent.y.x, "Normal is the tangent turned");
+		assert_eq! (line.normal.y.x, line.tangent.x.x);
+		
+		let scaled = Vec2::<Fx32> {
+			x: (end.x - start.x) * Fx32::from_q (1, 256),
+			y: (end.y - start.y) * Fx32::from_q (1, 256),
+		}.normalized ();
+		assert! (error (scaled.x.x, dx / exact_length) > 1 || error (scaled.y.x, dy / exact_length) > 1, "The old way really was lossy here");
+		
+		// Arc bounds are the whole circle, line bounds the whole capsule
+		let r = Fx32::from_int (8);
+		let around = |p: Vec2 <Fx32>| Aabb::from_points (p, p).expand (r);
+		assert_eq! (features [0].bounds, around (start));
+		assert_eq! (features [1].bounds, around (end));
+		assert_eq! (features [2].bounds, around (start).union (&around (end)));
+		
+		// Growing grows the bounds too, so the broadphase still finds it
+		assert_eq! (features [0].shrunk (-Fx32::from_int (2)).bounds, around (start).expand (Fx32::from_int (2)));
+	}
+	
+	#[test]
+	fn broadphase () {
+		let level = random_level (1, 500);
+		let world = Broadphase::new (&level);
+		
+		let mut hits = 0;
+		
+		for ray in random_rays (2, 5000, &level).iter () {
+			let result = world.trace (ray, |r| r);
+			
+			assert_eq! (
+				result,
+				world.trace_brute_force (ray, |r| r),
+				"Broadphase must match brute force");
+			
+			if result != Ray2TraceResult::Miss {
+				hits += 1;
+			}
+		}
+		
+		assert! (hits > 100, "Enough rays hit something to mean anything");
+		
+		// Obstacles so far apart the span doesn't fit in an i32, and
+		// 32-unit cells would need millions. The tracer can't take rays
+		// from one to the other, so just check each end.
+		let v = |x, y| Vec2 {x: Fx32::from_int (x), y: Fx32::from_int (y)};
+		let far = Broadphase::new (&[
+			PolyCapsule::new (&[v (-20000, -20000), v (-19900, -20000)], Fx32::from_int (8)),
+			PolyCapsule::new (&[v (19900, 20000), v (20000, 20000)], Fx32::from_int (8)),
+		]);
+		let hit = |result: Ray2TraceResult| result.contact ().and_then (|c| c.feature).map (|f| f.obstacle);
+		assert_eq! (hit (far.trace (&Ray2::new (v (-19950, -20020), v (0, 20)), |r| r)), Some (0));
+		assert_eq! (hit (far.trace (&Ray2::new (v (19950, 19980), v (0, 20)), |r| r)), Some (1));
+		assert_eq! (far.trace (&Ray2::new (v (0, 0), v (10, 10)), |r| r), Ray2TraceResult::Miss);
+	}
+	
+	// cargo test broadphase_bench -- --ignored --nocapture
+	#[test]
+	#[ignore]
+	fn broadphase_bench () {
+		use std::time::Instant;
+		
+		let level = random_level (1, 2000);
+		let world = Broadphase::new (&level);
+		let rays = random_rays (2, 20000, &level);
+		
+		let start = Instant::now ();
+		let grid: Vec <Ray2TraceResult> = rays.iter ().map (|ray| world.trace (ray, |r| r)).collect ();
+		println! ("Grid: {:?}", start.elapsed ());
+		
+		let start = Instant::now ();
+		let brute: Vec <Ray2TraceResult> = rays.iter ().map (|ray| world.trace_brute_force (ray, |r| r)).collect ();
+		println! ("Brute force: {:?}", start.elapsed ());
+		
+		assert! (grid == brute, "Broadphase must match brute force");
+	}
+	
+	#[test]
+	fn batch () {
+		let level = random_level (3, 500);
+		let world = Broadphase::new (&level);
+		let mut rays = random_rays (4, 5000, &level);
+		
+		// And some that start inside, to get pops
+		let mut state = 5;
+		for capsule in level.iter ().take (200) {
+			let line = &capsule.lines [0];
+			rays.push (Ray2::new (
+				line.start + (line.end - line.start) * Fx32::from_q (1, 2),
+				Vec2 {x: Fx32::from_q (random (&mut state, -800, 800), 16), y: Fx32::from_q (random (&mut state, -800, 800), 16)},
+			));
+		}
+		
+		let start_x: Vec <i32> = rays.iter ().map (|r| r.start.x.x).collect ();
+		let start_y: Vec <i32> = rays.iter ().map (|r| r.start.y.x).collect ();
+		let dir_x: Vec <i32> = rays.iter ().map (|r| r.get_dir ().x.x).collect ();
+		let dir_y: Vec <i32> = rays.iter ().map (|r| r.get_dir ().y.x).collect ();
+		let batch = RayBatch::new (&start_x, &start_y, &dir_x, &dir_y);
+		
+		let mut results = vec! [];
+		batch::trace_batch (&world, &batch, &mut results);
+		
+		// The one-off tracers don't know which obstacle they hit
+		let anonymous = |r: Ray2TraceResult| match r {
+			Ray2TraceResult::Hit (c) => Ray2TraceResult::Hit (Contact { feature: None, .. c }),
+			Ray2TraceResult::Pop (c) => Ray2TraceResult::Pop (Contact { feature: None, .. c }),
+			Ray2TraceResult::Miss => r,
+		};
+		
+		let mut kinds = [0; 3];
+		assert_eq! (results.len (), rays.len ());
+		for (ray, result) in rays.iter ().zip (results.iter ()) {
+			assert_eq! (*result, world.trace (ray, |r| r), "Batch must match one at a time");
+			
+			let folded = level.iter ()
+			.flat_map (|capsule| {
+				capsule.arcs.iter ().map (|a| raytrace::ray_trace_arc (ray, a))
+				.chain (capsule.lines.iter ().map (|l| raytrace::ray_trace_line_2 (ray, l)))
+				.collect::<Vec <_>> ()
+			})
+			.fold (Resolver::new (), Resolver::add)
+			.finish ();
+			assert_eq! (anonymous (*result), folded, "Batch must match tracing every arc and line");
+			
+			kinds [match *result {
+				Ray2TraceResult::Miss => 0,
+				Ray2TraceResult::Hit (..) => 1,
+				Ray2TraceResult::Pop (..) => 2,
+			}] += 1;
+		}
+		assert! (kinds.iter ().all (|k| *k > 50), "Plenty of misses, hits and pops {:?}", kinds);
+		
+		// Wraps instead of panicking
+		let mut dir_x = vec! [i32::max_value (), 0];
+		let mut dir_y = vec! [0, i32::min_value ()];
+		batch::accelerate (&mut dir_x, &mut dir_y, Vec2 {x: Fx32::from_int (1), y: -Fx32::from_int (1)});
+		assert_eq! (dir_x, vec! [i32::min_value () + Fx32::from_int (1).x - 1, Fx32::from_int (1).x]);
+		assert_eq! (dir_y [1], i32::max_value () - Fx32::from_int (1).x + 1);
+	}
+	
+	#[test]
+	fn contact () {
+		let v = |x, y| Vec2 {x: Fx32::from_int (x), y: Fx32::from_int (y)};
+		// CCD positions are only good to a few bits
+		let near = |a: Vec2 <Fx32>, b: Vec2 <Fx32>| (a - b).length_64 () < Fx32::from_q (1, 8);
+		
+		let world = Broadphase::new (&[
+			PolyCapsule::new (&[v (0, 0), v (10, 0)], Fx32::from_int (8)),
+			PolyCapsule::new (&[v (0, 100), v (100, 100), v (100, 0)], Fx32::from_int (8)),
+		]);
+		
+		match world.trace (&Ray2::new (v (50, 80), v (0, 20)), |r| r) {
+			Ray2TraceResult::Hit (c) => {
+				assert_eq! (c.feature, Some (FeatureRef {obstacle: 1, kind: FeatureKind::Line, index: 0}));
+				assert! ((Fx32::from (c.t) - Fx32::from_q (3, 5)).abs () < Fx32::from_q (1, 64), "Time of impact");
+				assert_eq! (c.depth, Fx32::from_int (0));
+				assert! (near (c.pos, v (50, 92)), "Hit position {:?}", c.pos);
+				assert! (near (c.point, v (50, 100)), "Hit point {:?}", c.point);
+			},
+			r => panic! ("Landing on the second segment {:?}", r),
+		}
+		
+		match world.trace (&Ray2::new (v (50, 95), v (0, 1)), |r| r) {
+			Ray2TraceResult::Pop (c) => {
+				assert_eq! (c.feature, Some (FeatureRef {obstacle: 1, kind: FeatureKind::Line, index: 0}));
+				assert_eq! (c.depth, Fx32::from_int (3));
+				assert_eq! (c.pos, v (50, 92));
+				assert_eq! (c.point, v (50, 100));
+			},
+			r => panic! ("Popping out of the second segment {:?}", r),
+		}
+		
+		match world.trace (&Ray2::new (v (-20, 0), v (16, 0)), |r| r) {
+			Ray2TraceResult::Hit (c) => {
+				assert_eq! (c.feature, Some (FeatureRef {obstacle: 0, kind: FeatureKind::Arc, index: 0}));
+				assert! (near (c.pos, v (-8, 0)), "Hit position {:?}", c.pos);
+				assert_eq! (c.point, v (0, 0));
+			},
+			r => panic! ("Hitting the end of the first capsule {:?}", r),
+		}
+	}
+	
+	// A ray that starts inside a line is popped out before anything
+	// else, but only if it's beside the line. Past the end it's the
+	// arc's job.
+	#[test]
+	fn line_pop () {
+		let v = |x, y| Vec2 {x: Fx32::from_int (x), y: Fx32::from_int (y)};
+		let line = WideLine::new (v (0, 0), v (10, 0), Fx32::from_int (8));
+		
+		// Moving along the line, so it'd never reach the surface
+		match raytrace::ray_trace_line_2 (&Ray2::new (v (5, 2), v (1, 0)), &line) {
+			Ray2TraceResult::Pop (c) => {
+				assert_eq! (c.depth, Fx32::from_int (6));
+				assert_eq! (c.pos, v (5, 8));
+			},
+			r => panic! ("Popping out while sliding along {:?}", r),
+		}
+		
+		// Inside, past the end, heading back over the line
+		let ray = Ray2::new (v (12, 2), v (-4, -1));
+		assert_eq! (raytrace::ray_trace_line_2 (&ray, &line), Ray2TraceResult::Miss);
+		
+		let world = Broadphase::new (&[PolyCapsule::new (&[v (0, 0), v (10, 0)], Fx32::from_int (8))]);
+		match world.trace (&ray, |r| r) {
+			Ray2TraceResult::Pop (c) => {
+				assert_eq! (c.feature.map (|f| f.kind), Some (FeatureKind::Arc));
+				assert_eq! (c.point, v (10, 0));
+			},
+			r => panic! ("Popping out of the end {:?}", r),
+		}
+	}
+	
+	#[test]
+	fn raycast () {
+		let v = |x, y| Vec2 {x: Fx32::from_int (x), y: Fx32::from_int (y)};
+		let wall = |x| PolyCapsule::new (&[v (x, -50), v (x, 50)], Fx32::from_int (8));
+		// CCD distances are only good to a few bits
+		let near = |a: Fx32, b: i32| (a - Fx32::from_int (b)).abs () < Fx32::from_q (1, 8);
+		
+		let world = Broadphase::new (&[wall (500), wall (100), wall (300)]);
+		let right = v (1, 0);
+		
+		let hit = query::raycast (&world, v (0, 0), right, Fx32::from_int (1000), Fx32::from_int (0)).unwrap ();
+		assert! (near (hit.distance, 92), "Hits the inflated wall {:?}", hit);
+		assert_eq! (hit.contact.feature.unwrap ().obstacle, 1);
+		assert! (! hit.inside);
+		
+		let hit = query::raycast (&world, v (0, 0), right, Fx32::from_int (1000), Fx32::from_int (8)).unwrap ();
+		assert! (near (hit.distance, 100), "Thin rays hit the centerline {:?}", hit);
+		
+		assert_eq! (query::raycast (&world, v (0, 0), right, Fx32::from_int (50), Fx32::from_int (0)), None, "Stops at max_distance");
+		assert_eq! (query::raycast (&world, v (0, 0), v (0, 1), Fx32::from_int (1000), Fx32::from_int (0)), None, "Nothing down there");
+		
+		let hit = query::raycast (&world, v (96, 0), right, Fx32::from_int (1000), Fx32::from_int (0)).unwrap ();
+		assert! (hit.inside, "Starting inside a wall");
+		assert_eq! (hit.distance, Fx32::from_int (0));
+		assert_eq! (hit.contact.depth, Fx32::from_int (4));
+		
+		let mut hits = vec! [];
+		query::raycast_all (&world, v (0, 0), right, Fx32::from_int (1000), Fx32::from_int (0), &mut hits);
+		
+		let obstacles: Vec <u32> = hits.iter ().map (|h| h.contact.feature.unwrap ().obstacle).collect ();
+		assert_eq! (obstacles, vec! [1, 2, 0], "Sorted by distance");
+		assert! (near (hits [0].distance, 92));
+		assert! (near (hits [1].distance, 292));
+		assert! (near (hits [2].distance, 492));
+		
+		query::raycast_all (&world, v (0, 0), right, Fx32::from_int (200), Fx32::from_int (0), &mut hits);
+		assert_eq! (hits.len (), 1);
+	}
+	
+	#[test]
+	fn sweep () {
+		let v = |x, y| Vec2 {x: Fx32::from_int (x), y: Fx32::from_int (y)};
+		let near = |a: Vec2 <Fx32>, b: Vec2 <Fx32>| (a - b).length_64 () < Fx32::from_q (1, 8);
+		let pod = |x, y| PodVec2 {x: Fx32::from_int (x).x, y: Fx32::from_int (y).x};
+		
+		let ctx = cher_new (8.0, pod (0, 0));
+		let floor = [pod (-100, 50), pod (100, 50)];
+		cher_add_polycapsule (ctx, 2, floor.as_ptr ());
+		let context = unsafe { &mut*ctx };
+		
+		for &(radius, stop) in [(4, 46), (8, 42), (40, 10)].iter () {
+			match context.sweep_circle (v (0, 0), v (0, 100), Fx32::from_int (radius)) {
+				query::Sweep::Hit (c) => {
+					assert! (near (c.pos, v (0, stop)), "Radius {} stops at {:?}", radius, c.pos);
+					assert! (near (c.point, v (0, 50)));
+					assert_eq! (c.normal, v (0, -1).to_small ());
+				},
+				r => panic! ("Radius {} should hit the floor {:?}", radius, r),
+			}
+		}
+		
+		assert_eq! (context.sweep_circle (v (0, 0), v (0, 10), Fx32::from_int (4)), query::Sweep::Clear);
+		
+		match context.sweep_circle (v (0, 48), v (0, -10), Fx32::from_int (4)) {
+			query::Sweep::Overlap (c) => {
+				assert_eq! (c.depth, Fx32::from_int (2), "Overlaps even when moving away");
+				assert_eq! (c.normal, v (0, -1).to_small ());
+			},
+			r => panic! ("Starting inside the floor {:?}", r),
+		}
+		
+		let mut out = PodTraceResult {
+			kind: -1, t: 0, pos: pod (0, 0), normal: pod (0, 0),
+			obstacle: -1, feature_kind: 0, feature_index: 0, depth: 0, point: pod (0, 0),
+		};
+		assert_eq! (cher_sweep_circle (ctx, pod (0, 0), pod (0, 100), Fx32::from_int (4).x, &mut out), 1);
+		assert_eq! (out.obstacle, 0);
+		
+		cher_delete (ctx);
+	}
 	
 	#[test]
 	fn svg () {
-		raytrace::test_ray_trace ("lines.obj", Fx32::from_q (0, 1)).unwrap ();
+		use std::fs::File;
+		use std::io::BufWriter;
+		use std::io::Write;
+		
+		let scenario = Scenario::load ("scenarios/ccd.txt").unwrap ();
+		assert_eq! (scenario.spawns.len (), 128);
+		
+		let result = scenario.run ();
+		assert_eq! (result.trajectories.len (), 128);
+		assert_eq! (result.stats.slips, 0, "Every tick finishes");
+		assert! (result.stats.hits > 1000);
+		let num_points: usize = result.trajectories.iter ().map (|t| t.len () - 1).sum ();
+		assert_eq! (result.stats.traces as usize, num_points, "One trace per point after the spawn");
+		assert! (result.stats.max_subticks <= scenario.config.subticks);
+		assert! (result.stats.max_depth >= 0);
+		
+		// Kept out of the source tree, for looking at by hand
+		let out_dir = ::std::env::temp_dir ();
+		let mut writer = BufWriter::new (File::create (out_dir.join ("lines.obj")).unwrap ());
+		result.write_obj (&mut writer).unwrap ();
+		
+		let mut picture = vec! [];
+		svg::write_svg (&mut picture, &scenario.obstacles, &result).unwrap ();
+		let picture = String::from_utf8 (picture).unwrap ();
+		
+		let num_lines: usize = scenario.obstacles.iter ().map (|c| c.lines.len ()).sum ();
+		assert_eq! (picture.matches ("<polygon").count (), num_lines, "One rectangle per line");
+		let num_arcs: usize = scenario.obstacles.iter ().map (|c| c.arcs.len ()).sum ();
+		assert_eq! (picture.matches ("<circle class=\"obstacle\"").count (), num_arcs, "One whole circle per arc");
+		assert_eq! (picture.matches ("<circle class=\"hit\"").count (), result.stats.hits as usize, "One marker per hit");
+		assert_eq! (picture.matches ("<circle class=\"pop\"").count (), result.stats.pops as usize, "One marker per pop");
+		
+		File::create (out_dir.join ("lines.svg")).unwrap ().write_all (picture.as_bytes ()).unwrap ();
+	}
+	
+	#[test]
+	fn scenario_errors () {
+		match Scenario::parse ("gravity 0 1/8\nradius 8\ncapsule 0 0 10\n") {
+			Err (ScenarioError::Parse (3, _)) => (),
+			_ => panic! ("Odd number of coordinates"),
+		}
+		
+		match Scenario::parse ("# fine\n\nwarp 9\n") {
+			Err (ScenarioError::Parse (3, _)) => (),
+			_ => panic! ("Unknown directive"),
+		}
+		
+		let scenario = Scenario::parse ("ticks 10\nspawn 0 0 0.5 -1/4 # trailing comment\n").unwrap ();
+		assert_eq! (scenario.ticks, 10);
+		assert_eq! (scenario.spawns [0].vel, Vec2 {x: Fx32::from_q (1, 2), y: Fx32::from_q (-1, 4)});
+		
+		match Scenario::parse ("restitution 1/2\npop_restitution 3/2\n") {
+			Err (ScenarioError::Parse (2, _)) => (),
+			_ => panic! ("Restitution over 1"),
+		}
+		
+		let scenario = Scenario::parse ("pop_restitution 1/4\nmax_speed 12\n").unwrap ();
+		assert_eq! (scenario.config.pop_restitution.x, Fx32::from_q (1, 4));
+		assert_eq! (scenario.config.max_speed, Some (Fx32::from_int (12)));
+		
+		// Too big for an Fx32, in every way a number can be written
+		for (text, problem) in [
+			("radius 100000\n", "100000 is out of range"),
+			("radius 100000/2\n", "100000/2 is out of range"),
+			("radius 1/0\n", "1/0 divides by zero"),
+			("radius 40000.5\n", "40000.5 is out of range"),
+			("radius inf\n", "inf is out of range"),
+			("radius NaN\n", "NaN is out of range"),
+		].iter () {
+			match Scenario::parse (text) {
+				Err (ScenarioError::Parse (1, ref message)) => assert_eq! (message, problem),
+				_ => panic! ("{:?} should be rejected", text),
+			}
+		}
+		assert! (Scenario::parse ("material -32768 0 0 0\n").is_err ());
+		let scenario = Scenario::parse ("radius 32767\nspawn -32768 1/65536 -0.5 0\n").unwrap ();
+		assert_eq! (scenario.spawns [0].pos, Vec2 {x: Fx32::from_int (-32768), y: Fx32 {x: 1}});
+		
+		match Scenario::parse ("spawn_row 0 0 1 100000 0 0\n") {
+			Err (ScenarioError::Parse (1, _)) => (),
+			_ => panic! ("Too many particles"),
+		}
+		match Scenario::parse ("spawn_row 30000 0 1000 10 0 0\n") {
+			Err (ScenarioError::Parse (1, ref message)) => assert_eq! (message, "spawn_row goes out of range"),
+			_ => panic! ("Row runs off the end of Fx32"),
+		}
+		assert_eq! (Scenario::parse ("spawn_row 0 0 8 4096 0 0\n").unwrap ().spawns.len (), 4096);
+	}
+	
+	#[test]
+	fn config () {
+		let pod = |x, y| PodVec2 {x: Fx32::from_int (x).x, y: Fx32::from_int (y).x};
+		let floor = [pod (-100, 50), pod (100, 50)];
+		
+		let ctx = cher_new (8.0, pod (0, 0));
+		cher_add_polycapsule (ctx, 2, floor.as_ptr ());
+		
+		assert_eq! (cher_get_gravity (ctx).y, Fx32::from_q (1, 8).x);
+		assert_eq! (cher_get_subticks (ctx), 4);
+		assert_eq! (cher_get_restitution (ctx), Fx32::from_int (1).x);
+		assert_eq! (cher_get_pop_restitution (ctx), 0);
+		assert_eq! (cher_get_max_speed (ctx), 0, "No cap to start with");
+		
+		cher_set_subticks (ctx, 0);
+		assert_eq! (cher_get_subticks (ctx), 1, "Always at least one trace");
+		cher_set_subticks (ctx, 4);
+		cher_set_restitution (ctx, Fx32::from_int (3).x);
+		assert_eq! (cher_get_restitution (ctx), Fx32::from_int (1).x, "Clamped to 1");
+		
+		// Falls a long way, so it's going fast enough to bounce
+		let bounce_height = |restitution| {
+			let ctx = cher_new (8.0, pod (0, -200));
+			cher_add_polycapsule (ctx, 2, floor.as_ptr ());
+			cher_set_gravity (ctx, PodVec2 {x: 0, y: Fx32::from_q (1, 2).x});
+			cher_set_restitution (ctx, restitution);
+			
+			let mut landed = false;
+			let mut highest = 1000;
+			for _ in 0..100 {
+				cher_step (ctx);
+				let y = Fx32 { x: cher_get_player (ctx).y }.to_i32 ();
+				if landed {
+					highest = cmp::min (highest, y);
+				}
+				landed = landed || cher_get_player_contact (ctx).kind != 0;
+			}
+			
+			cher_delete (ctx);
+			highest
+		};
+		assert! (bounce_height (Fx32::from_int (1).x) < 0, "Bounces up by default");
+		assert! (bounce_height (0) >= 41, "Doesn't bounce at all with 0");
+		
+		cher_set_gravity (ctx, PodVec2 {x: 0, y: Fx32::from_q (1, 2).x});
+		cher_set_max_speed (ctx, Fx32::from_int (2).x);
+		assert_eq! (cher_get_max_speed (ctx), Fx32::from_int (2).x);
+		let mut last = cher_get_player (ctx).y;
+		for _ in 0..20 {
+			cher_step (ctx);
+			let y = cher_get_player (ctx).y;
+			assert! (y - last <= Fx32::from_int (2).x, "Falls no faster than the cap");
+			last = y;
+		}
+		assert! (last > Fx32::from_int (30).x, "But it still falls");
+		
+		cher_set_max_speed (ctx, 0);
+		assert_eq! (cher_get_max_speed (ctx), 0);
+		
+		cher_delete (ctx);
+	}
+	
+	#[test]
+	fn material () {
+		let v = |x, y| Vec2::<Fx32> { x: Fx32::from_q (x, 4), y: Fx32::from_q (y, 4) };
+		let up = Vec2::<Fx32> { x: Fx32::from_int (0), y: Fx32::from_int (-1) }.to_small ();
+		let material = |restitution, static_friction, dynamic_friction, min_bounce_speed| Material {
+			restitution: Fx32::from_q (restitution, 4).to_small (),
+			static_friction: Fx32::from_q (static_friction, 4).to_small (),
+			dynamic_friction: Fx32::from_q (dynamic_friction, 4).to_small (),
+			min_bounce_speed: Fx32::from_q (min_bounce_speed, 4),
+		};
+		
+		// Landing at 2 down and 1 sideways gives an impulse of 2
+		let vel = v (4, 8);
+		
+		assert_eq! (material (0, 4, 0, 0).respond (vel, up), v (0, 0), "Static friction holds it still");
+		assert_eq! (material (0, 1, 1, 0).respond (vel, up), v (2, 0), "Dynamic friction slows it by half the impulse");
+		assert_eq! (material (0, 0, 0, 0).respond (vel, up), v (4, 0), "No friction slides freely");
+		assert_eq! (material (0, 0, 4, 0).respond (vel, up), v (0, 0), "Dynamic friction never reverses it");
+		
+		assert_eq! (material (4, 0, 0, 4).respond (vel, up), v (4, -8), "Fast enough to bounce");
+		assert_eq! (material (4, 0, 0, 12).respond (vel, up), v (4, 0), "Too slow to bounce");
+		assert_eq! (material (2, 0, 0, 0).respond (vel, up), v (4, -4));
+		
+		assert_eq! (material (4, 4, 4, 0).respond (v (4, -8), up), v (4, -8), "Leaving the surface isn't touched");
+		
+		let surface = material (2, 2, 1, 4);
+		assert_eq! (Material::neutral ().combine (&surface), surface, "Neutral particles let the surface decide");
+		assert_eq! (surface.combine (&Material::neutral ()), surface);
+		assert_eq! (surface.combine (&material (2, 4, 2, 8)), Material {
+			restitution: Fx32::from_q (1, 4).to_small (),
+			static_friction: Fx32::from_q (1, 2).to_small (),
+			dynamic_friction: Fx32::from_q (1, 8).to_small (),
+			min_bounce_speed: Fx32::from_int (2),
+		}, "Coefficients multiply and the higher cutoff wins");
+	}
+	
+	#[test]
+	fn polycapsule_segments () {
+		let v = |x, y| Vec2::<Fx32> { x: Fx32::from_int (x), y: Fx32::from_int (y) };
+		let points = [v (0, 0), v (10, 0), v (10, 10), v (20, 15)];
+		let capsule = PolyCapsule::new (&points, Fx32::from_int (2));
+		
+		assert_eq! (capsule.lines.len (), points.len () - 1, "One line per segment, no duplicates");
+		assert_eq! (capsule.arcs.len (), points.len ());
+		
+		for (i, line) in capsule.lines.iter ().enumerate () {
+			assert_eq! (line.start, points [i]);
+			assert_eq! (line.end, points [i + 1]);
+		}
+		for (arc, p) in capsule.arcs.iter ().zip (points.iter ()) {
+			assert_eq! (arc.circle.center, *p);
+		}
+	}
+	
+	#[test]
+	fn segment_material () {
+		let pod = |x, y| PodVec2 {x: Fx32::from_int (x).x, y: Fx32::from_int (y).x};
+		let pod_material = |restitution| PodMaterial {
+			restitution: restitution,
+			static_friction: 0,
+			dynamic_friction: 0,
+			min_bounce_speed: 0,
+		};
+		
+		// How fast each particle leaves the floor the first time it
+		// touches it
+		let bounces = |ctx: *mut CherenkovSim| {
+			let mut bounces = vec! [None; unsafe { &*ctx }.particles.len ()];
+			
+			for _ in 0..100 {
+				cher_step (ctx);
+				
+				for (b, p) in bounces.iter_mut ().zip (unsafe { &*ctx }.particles.iter ()) {
+					if b.is_none () && p.contact != Ray2TraceResult::Miss {
+						*b = Some (p.ray.get_dir ().y);
+					}
+				}
+			}
+			
+			bounces.into_iter ().map (|b| b.unwrap ()).collect::<Vec <_>> ()
+		};
+		
+		let make_sim = || {
+			let ctx = cher_new (8.0, pod (-50, 0));
+			let floor = [pod (-100, 50), pod (0, 50), pod (100, 50)];
+			cher_add_polycapsule (ctx, 3, floor.as_ptr ());
+			cher_add_particle (ctx, pod (50, 0));
+			ctx
+		};
+		
+		let ctx = make_sim ();
+		let same = bounces (ctx);
+		assert_eq! (same [0], same [1], "Both segments start out the same");
+		assert! (same [0] < 0, "and bouncy");
+		cher_delete (ctx);
+		
+		let ctx = make_sim ();
+		cher_set_segment_material (ctx, 0, 1, pod_material (0));
+		let dead = bounces (ctx);
+		assert_eq! (dead [0], same [0], "Segment 0 is left alone");
+		assert_eq! (dead [1], Fx32::from_int (0), "Segment 1 doesn't bounce");
+		cher_delete (ctx);
+		
+		let ctx = make_sim ();
+		cher_set_obstacle_material (ctx, 0, pod_material (Fx32::from_int (1).x));
+		cher_set_player_material (ctx, pod_material (0));
+		let player_dead = bounces (ctx);
+		assert_eq! (player_dead [0], Fx32::from_int (0), "The player's material multiplies the obstacle's");
+		assert! (player_dead [1] < same [1], "A bouncier obstacle bounces the other particle higher");
+		
+		// Out of range values from the host are clamped instead of
+		// panicking
+		let wild = || PodMaterial {
+			restitution: Fx32::from_int (3).x,
+			static_friction: i32::min_value (),
+			dynamic_friction: i32::max_value (),
+			min_bounce_speed: 0,
+		};
+		cher_set_player_material (ctx, wild ());
+		cher_set_obstacle_material (ctx, 0, wild ());
+		cher_set_segment_material (ctx, 0, 1, wild ());
+		let m = unsafe { &*ctx }.particles [0].material;
+		assert_eq! (m.restitution, Fx32::from_int (1).to_small ());
+		assert_eq! (m.static_friction, Fx32::from_int (0).to_small ());
+		assert_eq! (m.dynamic_friction, Fx32::from_int (1).to_small ());
+		assert_eq! (unsafe { &*ctx }.world.obstacles [0].lines [1].material, m);
+		cher_delete (ctx);
+	}
+	
+	#[test]
+	fn one_way () {
+		let v = |x, y| Vec2 {x: Fx32::from_int (x), y: Fx32::from_int (y)};
+		
+		let platform = PolyCapsule::new (&[v (0, 0), v (100, 0)], Fx32::from_int (8)).one_way (v (0, -1).normalized ());
+		
+		let trace = |ray: Ray2| {
+			let point_results = platform.arcs.iter ().map (|arc| raytrace::ray_trace_arc (&ray, arc));
+			let line_results = platform.lines.iter ().map (|line| raytrace::ray_trace_line_2 (&ray, line));
+			
+			point_results.chain (line_results).fold (Ray2TraceResult::Miss, Ray2TraceResult::fold)
+		};
+		
+		match trace (Ray2::new (v (50, -20), v (0, 30))) {
+			Ray2TraceResult::Hit (..) => (),
+			_ => panic! ("Landing from above"),
+		}
+		
+		match trace (Ray2::new (v (50, 20), v (0, -30))) {
+			Ray2TraceResult::Miss => (),
+			_ => panic! ("Jumping up from below"),
+		}
+		
+		match trace (Ray2::new (v (50, -2), v (0, 1))) {
+			Ray2TraceResult::Miss => (),
+			_ => panic! ("Falling back halfway through"),
+		}
+		
+		match trace (Ray2::new (v (50, -7), v (0, 1))) {
+			Ray2TraceResult::Pop (..) => (),
+			_ => panic! ("Resting slightly inside"),
+		}
+	}
+	
+	#[test]
+	fn resting_contact () {
+		let v = |x, y| Vec2 {x: Fx32::from_int (x), y: Fx32::from_int (y)};
+		
+		// A V-shaped crease made of two separate capsules, so the
+		// particle ends up touching both at once
+		let obstacles = vec! [
+			PolyCapsule::new (&[v (-50, 0), v (0, 50)], Fx32::from_int (8)),
+			PolyCapsule::new (&[v (0, 50), v (50, 0)], Fx32::from_int (8)),
+		];
+		let world = Broadphase::new (&obstacles);
+		let config = PhysicsConfig::default ();
+		let mut material = Material::neutral ();
+		material.restitution = Fx32::from_int (0).to_small ();
+		
+		let mut particle = Ray2::new (v (-30, 0), v (0, 0));
+		
+		for tick in 0..400 {
+			let remaining_dt = solver::step_particle (&world, &mut vec! [], &config, &mut particle, &material, Fx32::from_int (0), Fx32::from_int (1), |_, _, _, _| ());
+			
+			if tick > 200 {
+				assert! (remaining_dt == 0, "Slipped at tick {}", tick);
+			}
+		}
+		
+		assert! (particle.get_dir ().length () < Fx32::from_q (1, 64), "Came to rest");
+		assert! (particle.start.y < 50, "Stayed in the crease");
+	}
+	
+	#[test]
+	fn contact_speed () {
+		let v = |x, y| Vec2 {x: Fx32::from_int (x), y: Fx32::from_int (y)};
+		
+		let obstacles = vec! [PolyCapsule::new (&[v (-50, 50), v (50, 50)], Fx32::from_int (8))];
+		let world = Broadphase::new (&obstacles);
+		
+		// Velocity right after landing at 1/8 per tick on a default
+		// obstacle, which has a restitution of 1/2
+		let landing = |contact_speed| {
+			let config = PhysicsConfig {
+				gravity: v (0, 0),
+				contact_speed: contact_speed,
+				.. PhysicsConfig::default ()
+			};
+			let mut particle = Ray2::new (Vec2 { x: Fx32::from_int (0), y: Fx32::from_q (83, 2) }, Vec2 { x: Fx32::from_int (0), y: Fx32::from_q (1, 8) });
+			let mut landed = None;
+			
+			for _ in 0..10 {
+				solver::step_particle (&world, &mut vec! [], &config, &mut particle, &Material::neutral (), Fx32::from_int (0), Fx32::from_int (1), |p, _, _, result| {
+					if landed.is_none () && result.contact ().is_some () {
+						landed = Some (p.get_dir ());
+					}
+				});
+			}
+			
+			landed.unwrap ()
+		};
+		
+		assert_eq! (PhysicsConfig::default ().contact_speed, Fx32::from_q (1, 4));
+		assert_eq! (landing (PhysicsConfig::default ().contact_speed).y, Fx32::from_int (0), "Slow hits stop dead by default");
+		assert_eq! (landing (Fx32::from_int (0)).y, -Fx32::from_q (1, 16), "and bounce like they used to without contact_speed");
+	}
+	
+	#[test]
+	fn sleeping () {
+		let pod = |x, y| PodVec2 {x: Fx32::from_int (x).x, y: Fx32::from_int (y).x};
+		
+		let ctx = cher_new (8.0, pod (0, 0));
+		let floor = [pod (-100, 50), pod (100, 50)];
+		cher_add_polycapsule (ctx, 2, floor.as_ptr ());
+		
+		for _ in 0..300 {
+			cher_step (ctx);
+		}
+		assert_eq! (cher_is_sleeping (ctx), 1, "Fell asleep on the floor");
+		
+		let before = cher_get_player (ctx);
+		cher_step (ctx);
+		assert_eq! (cher_get_player (ctx).y, before.y, "Sleeping particles don't move");
+		
+		cher_set_gravity (ctx, PodVec2 {x: 0, y: Fx32::from_q (1, 16).x});
+		assert_eq! (cher_is_sleeping (ctx), 0, "Woken by gravity");
+		
+		for _ in 0..300 {
+			cher_step (ctx);
+		}
+		
+		let ceiling = [pod (-100, -20), pod (100, -20)];
+		cher_add_polycapsule (ctx, 2, ceiling.as_ptr ());
+		assert_eq! (cher_is_sleeping (ctx), 1, "Far away obstacles don't wake it");
+		
+		cher_add_polycapsule (ctx, 2, floor.as_ptr ());
+		assert_eq! (cher_is_sleeping (ctx), 0, "Nearby obstacles do");
+		
+		cher_delete (ctx);
+		
+		// Without gravity, a slow particle drifts forever
+		let ctx = cher_new (8.0, pod (0, 0));
+		cher_set_gravity (ctx, pod (0, 0));
+		cher_set_velocity (ctx, 0, PodVec2 {x: Fx32::from_q (1, 16).x, y: 0});
+		
+		for _ in 0..300 {
+			cher_step (ctx);
+			assert_eq! (cher_is_sleeping (ctx), 0, "Drifting isn't resting");
+		}
+		assert! (cher_get_player (ctx).x > Fx32::from_int (15).x);
+		
+		cher_delete (ctx);
+		
+		// and nothing sleeps at the top of a jump, however short
+		// sleep_ticks is
+		let ctx = cher_new (8.0, pod (0, 0));
+		unsafe { &mut*ctx }.config.sleep_ticks = 1;
+		cher_set_velocity (ctx, 0, pod (0, -2));
+		
+		for _ in 0..40 {
+			cher_step (ctx);
+			assert_eq! (cher_is_sleeping (ctx), 0, "In the air");
+		}
+		
+		cher_delete (ctx);
+	}
+	
+	#[test]
+	fn step_stats () {
+		let pod = |x, y| PodVec2 {x: Fx32::from_int (x).x, y: Fx32::from_int (y).x};
+		
+		let ctx = cher_new (8.0, pod (0, 0));
+		let floor = [pod (-100, 50), pod (100, 50)];
+		cher_add_polycapsule (ctx, 2, floor.as_ptr ());
+		cher_add_particle (ctx, pod (1000, 0));
+		
+		for _ in 0..10 {
+			cher_step (ctx);
+		}
+		
+		let stats = cher_get_stats (ctx);
+		assert_eq! (stats.particles, 20, "Both particles were awake");
+		assert_eq! (stats.hits + stats.pops, 0, "Nothing to hit yet");
+		assert_eq! (stats.traces, 20, "Falling freely takes one subtick");
+		assert_eq! (stats.max_subticks, 1);
+		
+		cher_reset_stats (ctx);
+		assert_eq! (unsafe { &*ctx }.stats, StepStats::default ());
+		
+		for _ in 0..300 {
+			cher_step (ctx);
+		}
+		
+		let stats = cher_get_stats (ctx);
+		assert! (stats.hits > 0, "Landed on the floor");
+		assert! (stats.traces >= stats.hits + stats.pops);
+		assert! (stats.particles < 600, "The player fell asleep on the floor");
+		assert! (stats.max_subticks <= PhysicsConfig::default ().subticks);
+		assert! (stats.max_depth >= 0);
+		
+		// Counters stick at the top instead of overflowing
+		let full = StepStats {
+			particles: u32::max_value (),
+			traces: u32::max_value (),
+			hits: u32::max_value (),
+			pops: 0,
+			max_subticks: 0,
+			slips: u32::max_value (),
+			max_depth: Fx32::from_int (0),
+		};
+		let mut more = full.add (unsafe { &*ctx }.stats);
+		more.record (&Ray2TraceResult::Miss);
+		assert_eq! ((more.particles, more.traces, more.hits, more.slips), (u32::max_value (), u32::max_value (), u32::max_value (), u32::max_value ()));
+		
+		cher_delete (ctx);
+	}
+	
+	#[test]
+	fn shared_world () {
+		let pod = |x, y| PodVec2 {x: Fx32::from_int (x).x, y: Fx32::from_int (y).x};
+		let floor = [pod (-100, 50), pod (100, 50)];
+		let ceiling = [pod (-100, -50), pod (100, -50)];
+		
+		let world = cher_world_new (8.0);
+		cher_world_add_polycapsule (world, 2, floor.as_ptr ());
+		
+		let sims: Vec <*mut CherenkovSim> = (0..3).map (|_| cher_new_in_world (world, pod (0, 0))).collect ();
+		for sim in sims.iter () {
+			assert! (sync::Arc::ptr_eq (&unsafe { &**sim }.world, unsafe { &*world }), "Shared, not copied");
+		}
+		
+		// Adding to the world afterwards only changes the world
+		cher_world_add_one_way_polycapsule (world, 2, ceiling.as_ptr (), pod (0, -1));
+		assert_eq! (unsafe { &**world }.obstacles.len (), 2);
+		assert_eq! (unsafe { &*sims [0] }.world.obstacles.len (), 1);
+		assert! (sync::Arc::ptr_eq (&unsafe { &*sims [0] }.world, &unsafe { &*sims [1] }.world));
+		
+		// Predicting only reads the world, so it stays shared
+		let mut prediction: Vec <PodPrediction> = (0..10).map (|_| unsafe { ::std::mem::zeroed () }).collect ();
+		assert_eq! (cher_predict (sims [0], 0, 10, prediction.as_mut_ptr ()), 10);
+		assert! (sync::Arc::ptr_eq (&unsafe { &*sims [0] }.world, &unsafe { &*sims [1] }.world));
+		
+		// and so does changing one sim's obstacles
+		cher_add_polycapsule (sims [2], 2, ceiling.as_ptr ());
+		assert! (unsafe { &*sims [2] }.world.is_built (), "Rebuilt right away");
+		assert_eq! (unsafe { &*sims [2] }.world.obstacles.len (), 2);
+		assert_eq! (unsafe { &*sims [1] }.world.obstacles.len (), 1);
+		
+		// Rewinding a sim keeps it sharing, as long as the obstacles
+		// are the same
+		let snapshot = |ctx| {
+			let mut data = vec! [0u8; cher_snapshot_size (ctx) as usize];
+			cher_snapshot_write (ctx, data.len () as i32, data.as_mut_ptr ());
+			data
+		};
+		let data = snapshot (sims [0]);
+		assert_eq! (cher_snapshot_read (sims [0], data.len () as i32, data.as_ptr ()), 1);
+		assert! (sync::Arc::ptr_eq (&unsafe { &*sims [0] }.world, &unsafe { &*sims [1] }.world), "Still shared after rewinding");
+		assert_eq! (cher_snapshot_read (sims [2], data.len () as i32, data.as_ptr ()), 1);
+		assert_eq! (unsafe { &*sims [2] }.world.obstacles.len (), 1, "Gets its own copy of different obstacles");
+		
+		// Restoring into a world checks it's the right one
+		assert_eq! (cher_snapshot_read_in_world (sims [2], world, data.len () as i32, data.as_ptr ()), 0, "World has the ceiling too");
+		let late = cher_new_in_world (world, pod (0, 0));
+		let data = snapshot (late);
+		assert_eq! (cher_snapshot_read_in_world (sims [2], world, data.len () as i32, data.as_ptr ()), 1);
+		assert! (sync::Arc::ptr_eq (&unsafe { &*sims [2] }.world, unsafe { &*world }));
+		cher_delete (late);
+		
+		cher_world_delete (world);
+		
+		let alone = cher_new (8.0, pod (0, 0));
+		cher_add_polycapsule (alone, 2, floor.as_ptr ());
+		
+		for _ in 0..100 {
+			for ctx in sims.iter ().chain ([alone].iter ()) {
+				cher_step (*ctx);
+			}
+			
+			let expected = cher_get_player (alone);
+			for ctx in sims [..2].iter () {
+				let actual = cher_get_player (*ctx);
+				assert_eq! ((actual.x, actual.y), (expected.x, expected.y), "Sharing doesn't change the physics");
+			}
+		}
+		assert! (cher_get_player (alone).y > Fx32::from_int (40).x, "Landed on the floor");
+		
+		for ctx in sims.into_iter ().chain (Some (alone)) {
+			cher_delete (ctx);
+		}
+	}
+	
+	#[test]
+	fn variable_dt () {
+		let v = |x, y| Vec2::<Fx32> {x: Fx32::from_int (x), y: Fx32::from_int (y)};
+		let one = Fx32::from_int (1);
+		let half = Fx32::from_q (1, 2);
+		
+		let mut world = World::new (Fx32::from_int (8));
+		world.add_obstacle (PolyCapsule::new (&[v (-500, 200), v (500, 200)], Fx32::from_int (8)));
+		let world = sync::Arc::new (world);
+		
+		// Without friction, anything moving sideways slides forever
+		let new_sim = |sideways| {
+			let mut sim = CherenkovSim::new (world.clone ());
+			for i in 0..8 {
+				sim.add_particle (Particle::new (v (i * 50, 0), v ((i - 4) * sideways, -i), Fx32::from_int (8)));
+			}
+			sim
+		};
+		
+		// Half steps fall short of a whole step by gravity / 4, since
+		// the second half is already going faster. Rounding adds a bit.
+		let gravity = PhysicsConfig::default ().gravity.y;
+		let pos_tolerance = gravity.x / 4 + Fx32::from_q (1, 64).x;
+		let vel_tolerance = Fx32::from_q (1, 64).x;
+		
+		let mut whole = new_sim (1);
+		let mut halves = new_sim (1);
+		
+		for _ in 0..60 {
+			halves.particles = whole.particles.clone ();
+			
+			whole.step_dt (one);
+			halves.step_dt (half);
+			halves.step_dt (half);
+			
+			for (a, b) in whole.particles.iter ().zip (halves.particles.iter ()) {
+				if a.contact != Ray2TraceResult::Miss || b.contact != Ray2TraceResult::Miss {
+					// Landing a bit sooner or later can't be compared
+					// this closely
+					continue;
+				}
+				
+				let (dp, dv) = (a.ray.start - b.ray.start, a.ray.get_dir () - b.ray.get_dir ());
+				assert! (dp.x.x.abs () <= pos_tolerance && dp.y.x.abs () <= pos_tolerance, "Position off by {:?}", dp);
+				assert! (dv.x.x.abs () <= vel_tolerance && dv.y.x.abs () <= vel_tolerance, "Velocity off by {:?}", dv);
+			}
+		}
+		
+		// Left alone, they come to rest in about the same places, and
+		// fall asleep after about the same amount of time. Bouncing a
+		// little differently on the way down makes up the difference.
+		let mut whole = new_sim (0);
+		let mut halves = new_sim (0);
+		let mut fell_asleep = [[None; 8]; 2];
+		
+		for tick in 0i32..400 {
+			whole.step_dt (one);
+			halves.step_dt (half);
+			halves.step_dt (half);
+			
+			for (j, sim) in [&whole, &halves].iter ().enumerate () {
+				for (i, p) in sim.particles.iter ().enumerate () {
+					if p.sleeping && fell_asleep [j] [i].is_none () {
+						fell_asleep [j] [i] = Some (tick);
+					}
+				}
+			}
+		}
+		for i in 0..8 {
+			let (a, b) = (&whole.particles [i], &halves.particles [i]);
+			let (ta, tb) = (fell_asleep [0] [i].expect ("Came to rest"), fell_asleep [1] [i].expect ("Came to rest"));
+			
+			assert! ((ta - tb).abs () <= 8, "Fell asleep at {} and {}", ta, tb);
+			assert! ((a.ray.start.y - b.ray.start.y).abs () < Fx32::from_q (1, 8), "Resting at the same height");
+		}
+		
+		// Steps over a tick are split into whole ticks
+		let mut whole = new_sim (0);
+		let mut long = new_sim (0);
+		for _ in 0..50 {
+			whole.step_dt (one);
+			whole.step_dt (one);
+			whole.step_dt (half);
+			long.step_dt (Fx32::from_q (5, 2));
+		}
+		for (a, b) in whole.particles.iter ().zip (long.particles.iter ()) {
+			assert_eq! (a.ray.start, b.ray.start);
+		}
+		
+		// and a force pushes for the whole of a long step, the same as
+		// applying it before every tick
+		let push = Vec2 {x: Fx32::from_q (1, 4), y: Fx32::from_int (0)};
+		let mut whole = new_sim (0);
+		let mut long = new_sim (0);
+		for _ in 0..20 {
+			for _ in 0..3 {
+				whole.particles [0].apply_force (push);
+				whole.step_dt (one);
+			}
+			long.particles [0].apply_force (push);
+			long.step_dt (Fx32::from_int (3));
+		}
+		assert_eq! (whole.particles [0].ray.start, long.particles [0].ray.start);
+		assert_eq! (whole.particles [0].ray.get_dir (), long.particles [0].ray.get_dir ());
+		assert_eq! (long.particles [0].force, Vec2 {x: Fx32::from_int (0), y: Fx32::from_int (0)}, "and then stops");
+	}
+	
+	#[test]
+	fn controls () {
+		let pod = |x, y| PodVec2 {x: Fx32::from_int (x).x, y: Fx32::from_int (y).x};
+		let vel = |ctx: *mut CherenkovSim| unsafe { &*ctx }.particles [0].ray.get_dir ();
+		let air_time = |ctx: *mut CherenkovSim| unsafe { &*ctx }.particles [0].air_time.to_i32 ();
+		
+		let ctx = cher_new (8.0, pod (0, 0));
+		let floor = [pod (-1000, 50), pod (1000, 50)];
+		cher_add_polycapsule (ctx, 2, floor.as_ptr ());
+		
+		cher_set_velocity (ctx, 0, pod (0, 0));
+		cher_apply_impulse (ctx, 0, pod (3, 0));
+		cher_apply_impulse (ctx, 0, pod (1, 0));
+		assert_eq! (vel (ctx).x, Fx32::from_int (4), "Impulses add up right away");
+		
+		cher_set_velocity (ctx, 0, pod (0, 0));
+		cher_apply_force (ctx, 0, pod (0, -1));
+		cher_step (ctx);
+		assert_eq! (vel (ctx).y, Fx32::from_q (1, 8) - Fx32::from_int (1), "Forces act during the next step");
+		cher_step (ctx);
+		assert_eq! (vel (ctx).y, Fx32::from_q (2, 8) - Fx32::from_int (1), "and then stop");
+		
+		assert_eq! (cher_jump (ctx, 0, pod (4, 0).x, 100), 0, "Hasn't touched the ground yet");
+		
+		for _ in 0..300 {
+			cher_step (ctx);
+		}
+		assert_eq! (cher_jump (ctx, 0, pod (4, 0).x, 0), 1, "Standing on the floor");
+		assert_eq! (vel (ctx).y, Fx32::from_int (-4));
+		assert_eq! (cher_jump (ctx, 0, pod (4, 0).x, 100), 0, "No double jumps");
+		
+		// Settling down again lets it jump again, just after leaving the
+		// ground, but not long after
+		for _ in 0..300 {
+			cher_step (ctx);
+		}
+		// Resting contacts don't show up every single tick
+		let rested = air_time (ctx);
+		assert! (rested <= 2);
+		
+		cher_set_velocity (ctx, 0, pod (0, -2));
+		for _ in 0..3 {
+			cher_step (ctx);
+		}
+		assert_eq! (air_time (ctx), rested + 3);
+		assert_eq! (cher_jump (ctx, 0, pod (4, 0).x, rested + 2), 0, "Too late");
+		assert_eq! (cher_jump (ctx, 0, pod (4, 0).x, rested + 3), 1, "Still in time");
+		
+		// Walls aren't ground
+		let wall = [pod (100, -1000), pod (100, 1000)];
+		cher_add_polycapsule (ctx, 2, wall.as_ptr ());
+		let h = cher_spawn_particle (ctx, pod (80, -200), pod (4, 0), pod (8, 0).x, 0);
+		let hit_wall = (0..10).any (|_| {
+			cher_step (ctx);
+			unsafe { &*ctx }.particle (h as u32).unwrap ().contact != Ray2TraceResult::Miss
+		});
+		assert! (hit_wall);
+		assert_eq! (cher_jump (ctx, h, pod (4, 0).x, 100), 0);
+		
+		cher_delete (ctx);
+	}
+	
+	#[test]
+	fn contact_events () {
+		let pod = |x, y| PodVec2 {x: Fx32::from_int (x).x, y: Fx32::from_int (y).x};
+		
+		let ctx = cher_new (8.0, pod (0, 0));
+		let floor = [pod (-100, 50), pod (100, 50)];
+		cher_add_polycapsule (ctx, 2, floor.as_ptr ());
+		
+		let mut out: Vec <PodContactEvent> = (0..16).map (|_| unsafe { ::std::mem::zeroed () }).collect ();
+		
+		let mut falling_speed = Fx32::from_int (0);
+		let landed = (0..100).any (|_| {
+			falling_speed = unsafe { &*ctx }.particles [0].ray.get_dir ().y;
+			cher_step (ctx);
+			cher_drain_events (ctx, 16, out.as_mut_ptr ()) > 0
+		});
+		assert! (landed);
+		
+		let e = &out [0];
+		assert_eq! (e.particle, 0);
+		assert_eq! (e.contact.kind, 1, "Fell onto the floor");
+		assert_eq! (e.contact.obstacle, 0);
+		assert! (e.contact.normal.y < 0, "Floor normal points up");
+		assert! (e.time > 0 && e.time <= Fx32::from_int (1).x, "Hit partway through the tick");
+		
+		// It was going at least as fast as at the start of the tick
+		assert! (e.impact_speed >= falling_speed.x);
+		assert_eq! (cher_drain_events (ctx, 16, out.as_mut_ptr ()), 0, "Draining empties the queue");
+		
+		// Nobody drains them, so the queue fills up
+		cher_set_event_capacity (ctx, 4);
+		cher_add_particle (ctx, pod (20, 0));
+		cher_add_particle (ctx, pod (-20, 0));
+		for _ in 0..300 {
+			cher_step (ctx);
+		}
+		assert_eq! (cher_drain_events (ctx, 16, out.as_mut_ptr ()), 4);
+		assert! (cher_take_dropped_events (ctx) > 0);
+		assert_eq! (cher_take_dropped_events (ctx), 0, "Taking resets the count");
+		
+		// predict doesn't make events
+		let mut prediction = vec! [];
+		let particle = unsafe { &*ctx }.particles [0];
+		unsafe { &mut*ctx }.predict (&particle, 10, &mut prediction);
+		assert_eq! (cher_drain_events (ctx, 16, out.as_mut_ptr ()), 0);
+		
+		cher_delete (ctx);
+	}
+	
+	#[test]
+	fn snapshot () {
+		let pod = |x, y| PodVec2 {x: Fx32::from_int (x).x, y: Fx32::from_int (y).x};
+		let same = |a: *mut CherenkovSim, b: *mut CherenkovSim| {
+			let a = unsafe { &*a };
+			let b = unsafe { &*b };
+			
+			assert_eq! (a.particles.len (), b.particles.len ());
+			for (a, b) in a.particles.iter ().zip (b.particles.iter ()) {
+				assert_eq! (a.handle, b.handle);
+				assert_eq! (a.ray.start, b.ray.start, "Restoring must give the same bits as never stopping");
+				assert_eq! (a.ray.get_dir (), b.ray.get_dir (), "Restoring must give the same bits as never stopping");
+				assert_eq! (a.ray.get_length (), b.ray.get_length ());
+				assert_eq! (a.sleeping, b.sleeping);
+				assert_eq! (a.still_time, b.still_time);
+				assert_eq! (a.contact, b.contact);
+				assert_eq! (a.ground_normal, b.ground_normal);
+				assert_eq! (a.air_time, b.air_time);
+			}
+		};
+		
+		let ctx = cher_new (8.0, pod (1000, 0));
+		{
+			let context = unsafe { &mut*ctx };
+			for capsule in random_level (7, 100) {
+				context.add_obstacle (capsule);
+			}
+		}
+		let ledge = [pod (900, 40), pod (1100, 40)];
+		cher_add_one_way_polycapsule (ctx, 2, ledge.as_ptr (), pod (0, -1));
+		cher_set_restitution (ctx, Fx32::from_q (3, 4).x);
+		cher_set_max_speed (ctx, Fx32::from_int (6).x);
+		
+		let mut state = 8;
+		for _ in 0..100 {
+			let h = cher_spawn_particle (ctx, pod (random (&mut state, 0, 2000), random (&mut state, 0, 1000)), pod (0, 0), pod (random (&mut state, 4, 12), 0).x, 0);
+			cher_apply_force (ctx, h, pod (random (&mut state, -2, 2), 0));
+		}
+		
+		for _ in 0..50 {
+			cher_step (ctx);
+		}
+		
+		let mut data = vec! [0u8; cher_snapshot_size (ctx) as usize];
+		assert_eq! (cher_snapshot_write (ctx, data.len () as i32 - 1, data.as_mut_ptr ()), 0, "Too small");
+		assert_eq! (cher_snapshot_write (ctx, data.len () as i32, data.as_mut_ptr ()), data.len () as i32);
+		
+		let restored = cher_new (8.0, pod (0, 0));
+		assert_eq! (cher_snapshot_read (restored, data.len () as i32, data.as_ptr ()), 1);
+		same (ctx, restored);
+		
+		for _ in 0..200 {
+			cher_step (ctx);
+			cher_step (restored);
+		}
+		same (ctx, restored);
+		
+		// Rewinding in place works too
+		assert_eq! (cher_snapshot_read (ctx, data.len () as i32, data.as_ptr ()), 1);
+		for _ in 0..200 {
+			cher_step (ctx);
+		}
+		same (ctx, restored);
+		
+		// Bad data leaves the sim alone
+		let before = cher_get_player (restored);
+		assert_eq! (cher_snapshot_read (restored, data.len () as i32 - 1, data.as_ptr ()), 0, "Truncated");
+		assert_eq! (cher_get_player (restored).x, before.x);
+		
+		match snapshot::read_snapshot (&mut &data [..8]) {
+			Err (SnapshotError::Io (_)) => (),
+			_ => panic! ("Truncated snapshots are an error"),
+		}
+		
+		let mut future = data.clone ();
+		future [4] += 1;
+		match snapshot::read_snapshot (&mut &future [..]) {
+			Err (SnapshotError::Version (2)) => (),
+			_ => panic! ("Snapshots from other versions are rejected"),
+		}
+		
+		let mut trailing = data.clone ();
+		trailing.push (0);
+		match snapshot::read_snapshot (&mut &trailing [..]) {
+			Err (SnapshotError::Invalid (_)) => (),
+			_ => panic! ("Trailing data is an error"),
+		}
+		
+		match snapshot::read_snapshot (&mut &b"not a snapshot" [..]) {
+			Err (SnapshotError::NotASnapshot) => (),
+			_ => panic! (),
+		}
+		
+		// Config the setters would never allow. With no obstacles,
+		// subticks starts at byte 24 and restitution at 40.
+		let empty = cher_new (8.0, pod (0, 0));
+		let mut data = vec! [];
+		snapshot::write_snapshot (&mut data, unsafe { &*empty }).unwrap ();
+		cher_delete (empty);
+		
+		let patched = |offset: usize, x: i32| {
+			let mut bad = data.clone ();
+			LittleEndian::write_i32 (&mut bad [offset..offset + 4], x);
+			match snapshot::read_snapshot (&mut &bad [..]) {
+				Err (SnapshotError::Invalid (message)) => message,
+				_ => panic! ("{} at byte {} should be rejected", x, offset),
+			}
+		};
+		
+		assert! (snapshot::read_snapshot (&mut &data [..]).is_ok ());
+		
+		// Capsules the rest of the sim can't index into
+		let v = |x, y| Vec2 {x: Fx32::from_int (x), y: Fx32::from_int (y)};
+		let capsule = PolyCapsule::new (&[v (0, 0), v (10, 0), v (10, 10)], Fx32::from_int (8));
+		let mut missing_arc = capsule.clone ();
+		missing_arc.arcs.pop ();
+		let mut no_lines = capsule.clone ();
+		no_lines.lines.clear ();
+		no_lines.arcs.truncate (1);
+		
+		for bad in [missing_arc, no_lines].iter () {
+			let mut world = World::new (Fx32::from_int (8));
+			world.obstacles.push (bad.clone ());
+			
+			let mut blob = data [..8].to_vec ();
+			snapshot::write_world (&mut blob, &world).unwrap ();
+			snapshot::write_state (&mut blob, unsafe { &*ctx }).unwrap ();
+			
+			match snapshot::read_snapshot (&mut &blob [..]) {
+				Err (SnapshotError::Invalid (message)) => assert_eq! (message, "capsule needs one more arc than lines"),
+				_ => panic! ("Capsules with {} arcs and {} lines are rejected", bad.arcs.len (), bad.lines.len ()),
+			}
+		}
+		assert_eq! (patched (24, 0), "subticks must be at least 1");
+		assert_eq! (patched (40, Fx32::from_q (3, 2).x), "restitution must be from 0 to 1");
+		assert_eq! (patched (44, -1), "restitution must be from 0 to 1");
+		assert_eq! (patched (40, Fx32::from_int (2).x), "small number out of range");
+		assert_eq! (patched (40, i32::min_value ()), "small number out of range");
+		
+		cher_delete (ctx);
+		cher_delete (restored);
+	}
+	
+	#[test]
+	fn state_hash () {
+		use std::io::Write;
+		
+		let pod = |x, y| PodVec2 {x: Fx32::from_int (x).x, y: Fx32::from_int (y).x};
+		let make_sim = || {
+			let ctx = cher_new (8.0, pod (0, 0));
+			let floor = [pod (-100, 50), pod (100, 50)];
+			cher_add_polycapsule (ctx, 2, floor.as_ptr ());
+			cher_add_particle (ctx, pod (20, -30));
+			ctx
+		};
+		
+		// Published FNV-1a test vector
+		let mut h = statehash::Fnv1a::new ();
+		h.write_all (b"a").unwrap ();
+		assert_eq! (h.hash, 0xaf63dc4c8601ec8c);
+		
+		let a = make_sim ();
+		let b = make_sim ();
+		for _ in 0..100 {
+			cher_step (a);
+			cher_step (b);
+			assert_eq! (cher_state_hash (a, 1), cher_state_hash (b, 1), "Same steps, same hash");
+		}
+		
+		// Pinned, so a hash that changes between runs, builds or
+		// platforms fails here. Update it if the physics or the snapshot
+		// layout changes on purpose.
+		assert_eq! (cher_state_hash (a, 0), 0x71c14dec5cb2fc8b);
+		
+		unsafe { &mut*b }.particles.reserve (1000);
+		unsafe { &mut*b }.world_mut ().obstacles.shrink_to_fit ();
+		assert_eq! (cher_state_hash (a, 1), cher_state_hash (b, 1), "Capacity doesn't matter");
+		
+		let before = cher_state_hash (a, 0);
+		let before_all = cher_state_hash (a, 1);
+		let flip = |f: &dyn Fn (&mut CherenkovSim, u32), obstacle: bool| {
+			for bit in 0..32 {
+				f (unsafe { &mut*a }, 1 << bit);
+				if obstacle {
+					assert_eq! (cher_state_hash (a, 0), before, "Obstacles are optional");
+				}
+				else {
+					assert! (cher_state_hash (a, 0) != before, "Every bit counts");
+				}
+				assert! (cher_state_hash (a, 1) != before_all, "Every bit counts");
+				f (unsafe { &mut*a }, 1 << bit);
+			}
+			assert_eq! (cher_state_hash (a, 1), before_all);
+		};
+		
+		flip (&|sim, bit| sim.particles [1].ray.start.x.x ^= bit as i32, false);
+		flip (&|sim, bit| {
+			let p = &mut sim.particles [0];
+			let dir = p.ray.get_dir ();
+			p.ray = Ray2::from_parts (p.ray.start, Vec2 { x: dir.x, y: Fx32 { x: dir.y.x ^ bit as i32 } }, p.ray.get_length ());
+		}, false);
+		flip (&|sim, bit| sim.particles [1].air_time.x ^= bit as i32, false);
+		flip (&|sim, bit| sim.particles [1].flags ^= bit, false);
+		flip (&|sim, bit| sim.config.gravity.y.x ^= bit as i32, false);
+		flip (&|sim, bit| sim.world_mut ().obstacles [0].lines [0].end.y.x ^= bit as i32, true);
+		
+		cher_delete (a);
+		cher_delete (b);
+	}
+	
+	#[test]
+	fn particle_handles () {
+		let pod = |x, y| PodVec2 {x: Fx32::from_int (x).x, y: Fx32::from_int (y).x};
+		let r = |x| Fx32::from_int (x).x;
+		
+		let ctx = cher_new (8.0, pod (0, 0));
+		let floor = [pod (-500, 50), pod (500, 50)];
+		cher_add_polycapsule (ctx, 2, floor.as_ptr ());
+		
+		let small = cher_spawn_particle (ctx, pod (100, 0), pod (0, 1), r (4), 0);
+		let big = cher_spawn_particle (ctx, pod (200, 0), pod (0, 1), r (16), 0);
+		let doomed = cher_add_particle (ctx, pod (300, 0));
+		let frozen = cher_spawn_particle (ctx, pod (400, 0), pod (0, 1), r (8), PARTICLE_FROZEN | 0x100);
+		
+		assert_eq! ((small, big, doomed, frozen), (1, 2, 3, 4));
+		
+		assert_eq! (cher_remove_particle (ctx, 0), 0, "Can't remove the player");
+		assert_eq! (cher_remove_particle (ctx, doomed), 1);
+		assert_eq! (cher_remove_particle (ctx, doomed), 0, "Already gone");
+		assert_eq! (cher_add_particle (ctx, pod (300, 0)), 5, "Handles aren't reused");
+		
+		let mut handles = [0; 8];
+		let n = cher_get_particle_handles (ctx, 8, handles.as_mut_ptr ());
+		assert_eq! (n, cher_particle_count (ctx));
+		assert_eq! (&handles [..n as usize], &[0, 1, 2, 4, 5], "Stepped in the order they were added");
+		
+		for _ in 0..200 {
+			cher_step (ctx);
+		}
+		
+		let rest = |h| Fx32 { x: cher_get_particle (ctx, h).y }.to_f64 ();
+		assert! ((rest (0) - 42.0).abs () < 0.5, "Same radius as the sim, {}", rest (0));
+		assert! ((rest (small) - 46.0).abs () < 0.5, "Small particles sit lower, {}", rest (small));
+		assert! ((rest (big) - 34.0).abs () < 0.5, "Big ones sit higher, {}", rest (big));
+		assert_eq! (rest (frozen), 0.0, "Frozen particles don't move");
+		assert_eq! (rest (doomed), 0.0, "Removed particles read as 0");
+		
+		let mut state: PodParticle = unsafe { ::std::mem::zeroed () };
+		assert_eq! (cher_get_particle_state (ctx, frozen, &mut state), 1);
+		assert_eq! ((state.handle, state.radius, state.flags), (frozen, r (8), PARTICLE_FROZEN | 0x100));
+		assert_eq! (cher_get_particle_state (ctx, doomed, &mut state), 0);
+		
+		cher_set_particle_flags (ctx, frozen, 0);
+		cher_set_particle_radius (ctx, big, r (4));
+		for _ in 0..200 {
+			cher_step (ctx);
+		}
+		assert! ((rest (frozen) - 42.0).abs () < 0.5, "Thawed out and fell");
+		assert! ((rest (big) - 46.0).abs () < 0.5, "Shrunk and settled lower, {}", rest (big));
+		
+		cher_delete (ctx);
+	}
+	
+	#[test]
+	fn predict () {
+		let pod = |x, y| PodVec2 {x: Fx32::from_int (x).x, y: Fx32::from_int (y).x};
+		let predict = |ctx, i, ticks| {
+			let mut out: Vec <PodPrediction> = (0..ticks).map (|_| unsafe { ::std::mem::zeroed () }).collect ();
+			assert_eq! (cher_predict (ctx, i, ticks as i32, out.as_mut_ptr ()), ticks as i32);
+			out
+		};
+		
+		let ctx = cher_new (8.0, pod (0, 0));
+		{
+			let context = unsafe { &mut*ctx };
+			for capsule in random_level (7, 40) {
+				context.add_obstacle (capsule);
+			}
+		}
+		cher_add_particle (ctx, pod (300, 100));
+		
+		let ticks = 400;
+		let predicted = [predict (ctx, 0, ticks), predict (ctx, 1, ticks)];
+		
+		// Launching it from where it is at the speed it's going is the
+		// same thing
+		let mut launched: Vec <PodPrediction> = (0..ticks).map (|_| unsafe { ::std::mem::zeroed () }).collect ();
+		cher_predict_launch (ctx, 1, pod (300, 100), pod (0, 1), ticks as i32, launched.as_mut_ptr ());
+		for (a, b) in launched.iter ().zip (predicted [1].iter ()) {
+			assert_eq! ((a.pos.x, a.pos.y), (b.pos.x, b.pos.y));
+		}
+		
+		assert_eq! (cher_get_player (ctx).y, 0, "Predicting doesn't move anything");
+		assert_eq! (cher_get_stats (ctx).traces, 0, "or count anything");
+		
+		let mut touched = false;
+		
+		for tick in 0..ticks {
+			cher_step (ctx);
+			
+			for i in 0..2 {
+				let p = &predicted [i] [tick];
+				let actual = &unsafe { &*ctx }.particles [i];
+				
+				assert_eq! ((p.pos.x, p.pos.y), (actual.ray.start.x.x, actual.ray.start.y.x), "Prediction must match stepping");
+				assert_eq! ((p.vel.x, p.vel.y), (actual.ray.get_dir ().x.x, actual.ray.get_dir ().y.x));
+				assert_eq! (p.sleeping != 0, actual.sleeping);
+				
+				match actual.contact.contact () {
+					Some (c) => {
+						assert_eq! ((p.contact.pos.x, p.contact.pos.y), (c.pos.x.x, c.pos.y.x));
+						touched = true;
+					},
+					None => assert_eq! (p.contact.kind, 0),
+				}
+			}
+		}
+		assert! (touched, "The level should get in the way somewhere");
+		
+		cher_delete (ctx);
+	}
+	
+	#[cfg (feature = "parallel")]
+	#[test]
+	fn parallel () {
+		let pod = |x, y| PodVec2 {x: Fx32::from_int (x).x, y: Fx32::from_int (y).x};
+		let make_sim = || {
+			let ctx = cher_new (8.0, pod (1000, 0));
+			let context = unsafe { &mut*ctx };
+			for capsule in random_level (5, 300) {
+				context.add_obstacle (capsule);
+			}
+			
+			let mut state = 6;
+			for _ in 0..500 {
+				cher_add_particle (ctx, pod (random (&mut state, 0, 2000), random (&mut state, 0, 2000)));
+			}
+			ctx
+		};
+		
+		let serial = make_sim ();
+		let parallel = make_sim ();
+		
+		for _ in 0..200 {
+			unsafe { &mut*serial }.step_serial (Fx32::from_int (1));
+			unsafe { &mut*parallel }.step_parallel (Fx32::from_int (1));
+		}
+		
+		{
+			let a = unsafe { &*serial };
+			let b = unsafe { &*parallel };
+			
+			for (a, b) in a.particles.iter ().zip (b.particles.iter ()) {
+				assert_eq! (a.ray.start, b.ray.start, "Parallel must give the same bits as serial");
+				assert_eq! (a.ray.get_dir (), b.ray.get_dir (), "Parallel must give the same bits as serial");
+				assert_eq! (a.sleeping, b.sleeping);
+			}
+			
+			assert_eq! (a.stats, b.stats, "Parallel must count the same as serial");
+			assert_eq! (a.events.events, b.events.events, "and give the same events in the same order");
+			assert_eq! (a.events.dropped, b.events.dropped);
+		}
+		
+		cher_delete (serial);
+		cher_delete (parallel);
+	}
+	
+	#[test]
+	fn resolver () {
+		let v = |x, y| Vec2 {x: Fx32::from_int (x), y: Fx32::from_int (y)};
+		let t = Fx32::from_q (1, 2).to_small ();
+		
+		let results = [
+			Ray2TraceResult::Hit (Contact::hit (t, v (0, 0), v (-8, 0), v (1, 0).to_small (), Material::default ())),
+			Ray2TraceResult::Hit (Contact::hit (t, v (0, 0), v (0, -8), v (0, 1).to_small (), Material::default ())),
+			Ray2TraceResult::Hit (Contact::hit (Fx32::from_q (3, 4).to_small (), v (0, 4), v (-8, 4), v (1, 0).to_small (), Material::default ())),
+			Ray2TraceResult::Miss,
+		];
+		
+		let resolve = |order: &[usize]| order.iter ().fold (Resolver::new (), |r, i| r.add (results [*i])).finish ();
+		let expected = resolve (&[0, 1, 2, 3]);
+		
+		match expected {
+			Ray2TraceResult::Hit (c) => {
+				let n: Vec2 <Fx32> = c.normal.into ();
+				assert! ((n - Vec2 {x: Fx32::from_q (181, 256), y: Fx32::from_q (181, 256)}).length_64 () < Fx32::from_q (1, 64), "Normals of the crease are averaged {:?}", n);
+			},
+			r => panic! ("Should hit the crease {:?}", r),
+		}
+		
+		for order in [[3, 2, 1, 0], [1, 3, 0, 2], [2, 0, 3, 1]].iter () {
+			assert_eq! (resolve (order), expected, "Order doesn't matter");
+		}
+	}
+	
+	#[test]
+	fn obstacle_order () {
+		let pod = |x, y| PodVec2 {x: Fx32::from_int (x).x, y: Fx32::from_int (y).x};
+		
+		let make_sim = |shuffle_seed: u32| {
+			let mut level = random_level (7, 300);
+			
+			if shuffle_seed != 0 {
+				let mut state = shuffle_seed;
+				for i in (1..level.len ()).rev () {
+					let j = random (&mut state, 0, i as i32 + 1) as usize;
+					level.swap (i, j);
+				}
+			}
+			
+			let ctx = cher_new (8.0, pod (1000, 0));
+			let context = unsafe { &mut*ctx };
+			for capsule in level {
+				context.add_obstacle (capsule);
+			}
+			
+			let mut state = 8;
+			for _ in 0..300 {
+				cher_add_particle (ctx, pod (random (&mut state, 0, 2000), random (&mut state, 0, 2000)));
+			}
+			ctx
+		};
+		
+		let sims: Vec <*mut CherenkovSim> = [0, 1, 2].iter ().map (|seed| make_sim (*seed)).collect ();
+		
+		for _ in 0..300 {
+			for ctx in sims.iter () {
+				unsafe { &mut**ctx }.step_serial (Fx32::from_int (1));
+			}
+		}
+		
+		{
+			let a = unsafe { &*sims [0] };
+			
+			for ctx in sims [1..].iter () {
+				let b = unsafe { &**ctx };
+				
+				for (a, b) in a.particles.iter ().zip (b.particles.iter ()) {
+					assert_eq! (a.ray.start, b.ray.start, "Obstacle order doesn't change trajectories");
+					assert_eq! (a.ray.get_dir (), b.ray.get_dir (), "Obstacle order doesn't change trajectories");
+				}
+			}
+		}
+		
+		for ctx in sims {
+			cher_delete (ctx);
+		}
 	}
 	
 	#[test]
@@ -184,7 +1900,7 @@ mod tests {
 			Fx32::from_int (-1).abs (),
 			Fx32::from_int (1),
 			"abs");
-		
-		
+
+
     }
 }
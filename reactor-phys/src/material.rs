@@ -0,0 +1,111 @@
+use fx32::Fx32;
+use fx32::Fx32Small;
+use vec2::Vec2;
+
+/*
+Surface properties for contact response.
+
+Both obstacles and particles have a Material, and they're combined
+when they touch. Restitution and friction are multiplied, so a
+particle with the neutral material lets the obstacle decide, and
+min_bounce_speed takes the bigger of the two.
+*/
+#[derive (Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Material {
+	// 0.0 is a dead stop, 1.0 bounces off with the same speed
+	pub restitution: Fx32Small,
+	// If the tangential speed is below static_friction times the
+	// normal impulse, the particle sticks
+	pub static_friction: Fx32Small,
+	// Otherwise the tangential speed loses dynamic_friction times
+	// the normal impulse
+	pub dynamic_friction: Fx32Small,
+	// Hits slower than this don't bounce at all
+	pub min_bounce_speed: Fx32,
+}
+
+impl Default for Material {
+	// What every obstacle had before materials existed
+	fn default () -> Material {
+		Material {
+			restitution: Fx32::from_q (512, 1024).to_small (),
+			static_friction: Fx32::from_int (0).to_small (),
+			dynamic_friction: Fx32::from_int (0).to_small (),
+			min_bounce_speed: Fx32::from_int (0),
+		}
+	}
+}
+
+impl Material {
+	// Has no effect when combined with another material
+	pub fn neutral () -> Material {
+		Material {
+			restitution: Fx32::from_int (1).to_small (),
+			static_friction: Fx32::from_int (1).to_small (),
+			dynamic_friction: Fx32::from_int (1).to_small (),
+			min_bounce_speed: Fx32::from_int (0),
+		}
+	}
+	
+	pub fn combine (&self, o: &Material) -> Material {
+		Material {
+			restitution: self.restitution * o.restitution,
+			static_friction: self.static_friction * o.static_friction,
+			dynamic_friction: self.dynamic_friction * o.dynamic_friction,
+			min_bounce_speed: if self.min_bounce_speed > o.min_bounce_speed {
+				self.min_bounce_speed
+			}
+			else {
+				o.min_bounce_speed
+			},
+		}
+	}
+	
+	// Returns the velocity after touching a surface with normal n.
+	// n should be normalized
+	pub fn respond (&self, vel: Vec2 <Fx32>, n: Vec2 <Fx32Small>) -> Vec2 <Fx32> {
+		let n = Vec2::<Fx32>::from (n);
+		
+		let projection = vel.dot_64 (n);
+		if projection >= 0 {
+			return vel;
+		}
+		
+		let restitution = if -projection < self.min_bounce_speed {
+			Fx32::from_int (0)
+		}
+		else {
+			self.restitution.x
+		};
+		
+		// Magnitude of the impulse along the normal
+		let impulse = -projection - projection.mul_64 (restitution);
+		let reflected = vel + n.scale_64 (impulse);
+		
+		if self.static_friction == 0 && self.dynamic_friction == 0 {
+			return reflected;
+		}
+		
+		let tangent = n.cross ();
+		let along = reflected.dot_64 (tangent);
+		
+		let new_along = if along.abs () <= impulse.mul_64 (self.static_friction.x) {
+			Fx32::from_int (0)
+		}
+		else {
+			let slowdown = impulse.mul_64 (self.dynamic_friction.x);
+			
+			if along.abs () <= slowdown {
+				Fx32::from_int (0)
+			}
+			else if along > 0 {
+				along - slowdown
+			}
+			else {
+				along + slowdown
+			}
+		};
+		
+		reflected + tangent.scale_64 (new_along - along)
+	}
+}
@@ -1,14 +1,28 @@
 extern crate reactor_phys;
-use reactor_phys::raytrace;
-use reactor_phys::fx32::Fx32;
+use reactor_phys::scenario::Scenario;
+use reactor_phys::svg::write_svg;
 
+use std::env;
+use std::fs::File;
+use std::io::BufWriter;
+
+// Usage: cherenkov-svg [scenario.txt]
 fn main () {
-	/*
-	for i in 0..16 {
-		let filename = format! ("lines-{}.obj", i);
-		raytrace::test_ray_trace (&filename, Fx32::from_q (i, 4)).unwrap ();
-	}
-	*/
-	
-	raytrace::test_ray_trace ("lines.obj", Fx32::from_q (0, 1)).unwrap ();
+	let filename = env::args ().nth (1).unwrap_or ("scenarios/ccd.txt".to_string ());
+	let scenario = Scenario::load (&filename).unwrap ();
+	
+	let result = scenario.run ();
+	
+	println! ("num_hits: {}", result.stats.hits);
+	println! ("num_pops: {}", result.stats.pops);
+	println! ("num_traces: {}", result.stats.traces);
+	println! ("max_subticks: {}", result.stats.max_subticks);
+	println! ("max_depth: {}", result.stats.max_depth.to_f64 ());
+	println! ("num_slips: {}", result.stats.slips);
+	
+	let mut writer = BufWriter::new (File::create ("lines.obj").unwrap ());
+	result.write_obj (&mut writer).unwrap ();
+	
+	let mut writer = BufWriter::new (File::create ("lines.svg").unwrap ());
+	write_svg (&mut writer, &scenario.obstacles, &result).unwrap ();
 }
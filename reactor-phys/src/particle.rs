@@ -0,0 +1,156 @@
+use fx32::Fx32;
+use fx32::Fx32Small;
+use material::Material;
+use ray2::Ray2;
+use raytraceresult::Ray2TraceResult;
+use vec2::Vec2;
+
+use std::cmp;
+
+// Frozen particles are skipped by the sim like sleeping ones, but
+// nothing wakes them up until the flag is cleared
+pub const PARTICLE_FROZEN: u32 = 1;
+
+#[derive (Clone, Copy, Debug)]
+pub struct Particle {
+	// Given out by CherenkovSim::add_particle and never reused
+	pub handle: u32,
+	// start is the position, dir is the velocity per tick
+	pub ray: Ray2,
+	pub radius: Fx32,
+	pub material: Material,
+	// PARTICLE_FROZEN, plus any other bits the game wants to keep
+	// with the particle. The sim ignores the rest.
+	pub flags: u32,
+	// Sleeping particles are skipped by the sim until something
+	// wakes them up
+	pub sleeping: bool,
+	// How long in a row it's been touching something and slower than
+	// sleep_speed, in ticks
+	pub still_time: Fx32,
+	// The last thing it touched during the last step it was awake,
+	// or Miss if it didn't touch anything
+	pub contact: Ray2TraceResult,
+	// Added to the velocity during the next step, times dt, then
+	// cleared
+	pub force: Vec2 <Fx32>,
+	// The normal of the last ground it stood on, until it jumps
+	pub ground_normal: Option <Vec2 <Fx32Small>>,
+	// Ticks since it last touched the ground. Stops counting at
+	// MAX_AIR_TIME.
+	pub air_time: Fx32,
+}
+
+pub const MAX_AIR_TIME: i32 = 1024;
+
+// Normals within 60 degrees of straight up count as ground. Without
+// gravity there's no up, so anything does.
+pub fn is_ground (normal: Vec2 <Fx32Small>, gravity: Vec2 <Fx32>) -> bool {
+	let g = gravity.length_64 ();
+	
+	g == Fx32::from_int (0) || Vec2::<Fx32>::from (normal).dot_64 (gravity) <= -g.mul_64 (Fx32::from_q (1, 2))
+}
+
+impl Particle {
+	pub fn new (start: Vec2 <Fx32>, vel: Vec2 <Fx32>, radius: Fx32) -> Particle {
+		Particle {
+			handle: 0,
+			ray: Ray2::new (start, vel),
+			radius: radius,
+			material: Material::neutral (),
+			flags: 0,
+			sleeping: false,
+			still_time: Fx32::from_int (0),
+			contact: Ray2TraceResult::Miss,
+			force: Vec2::<Fx32> {
+				x: Fx32::from_int (0),
+				y: Fx32::from_int (0),
+			},
+			ground_normal: None,
+			air_time: Fx32::from_int (MAX_AIR_TIME),
+		}
+	}
+	
+	pub fn frozen (&self) -> bool {
+		self.flags & PARTICLE_FROZEN != 0
+	}
+	
+	pub fn wake (&mut self) {
+		self.sleeping = false;
+		self.still_time = Fx32::from_int (0);
+	}
+	
+	pub fn apply_impulse (&mut self, impulse: Vec2 <Fx32>) {
+		self.ray = Ray2::new (self.ray.start, self.ray.get_dir () + impulse);
+		self.wake ();
+	}
+	
+	// Pushes for the next step only, so call it every tick to keep
+	// pushing
+	pub fn apply_force (&mut self, force: Vec2 <Fx32>) {
+		self.force = self.force + force;
+		self.wake ();
+	}
+	
+	pub fn set_velocity (&mut self, vel: Vec2 <Fx32>) {
+		self.ray = Ray2::new (self.ray.start, vel);
+		self.wake ();
+	}
+	
+	// Call after every step with the normal of any ground it touched
+	pub fn update_ground (&mut self, ground: Option <Vec2 <Fx32Small>>, dt: Fx32) {
+		match ground {
+			Some (n) => {
+				self.ground_normal = Some (n);
+				self.air_time = Fx32::from_int (0);
+			},
+			None => {
+				self.air_time = Fx32 { x: cmp::min ((self.air_time + dt).x, Fx32::from_int (MAX_AIR_TIME).x) };
+			},
+		}
+	}
+	
+	/*
+	Jumps off the last ground it stood on, if that was no more than
+	coyote_time ticks ago, so players can still jump just after
+	running off a ledge.
+	
+	Whatever velocity it had along the ground's normal is replaced by
+	speed, so it jumps straight off slopes and walls, and the same
+	height whether it was falling or not. Returns false if it
+	couldn't jump.
+	*/
+	pub fn jump (&mut self, speed: Fx32, coyote_time: Fx32) -> bool {
+		let normal = match self.ground_normal {
+			Some (n) if self.air_time <= coyote_time => Vec2::<Fx32>::from (n),
+			_ => return false,
+		};
+		
+		let vel = self.ray.get_dir ();
+		let along = vel.dot_64 (normal);
+		
+		self.set_velocity (vel + normal.scale_64 (speed - along));
+		
+		// No jumping again until it lands
+		self.ground_normal = None;
+		self.air_time = Fx32::from_int (MAX_AIR_TIME);
+		
+		true
+	}
+	
+	// Call after every step, with whether it touched anything and how
+	// long the step was. Only particles resting or sliding on something
+	// fall asleep, never ones drifting or at the top of a jump.
+	pub fn update_sleep (&mut self, touching: bool, sleep_speed: Fx32, sleep_ticks: u32, dt: Fx32) {
+		if touching && self.ray.get_dir ().length_64 () < sleep_speed {
+			self.still_time = self.still_time + dt;
+		}
+		else {
+			self.still_time = Fx32::from_int (0);
+		}
+		
+		if self.still_time >= Fx32::from_int (sleep_ticks as i32) {
+			self.sleeping = true;
+		}
+	}
+}
@@ -2,11 +2,13 @@ use arc::Arc;
 use circle::Circle;
 use fx32::Fx32;
 use fx32::Fx32Small;
+use material::Material;
 use ray2::Ray2;
 use raytraceresult::Ray2TraceResult;
 use vec2::Vec2;
 use wide_line::WideLine;
 
+#[derive (Clone)]
 pub struct PolyCapsule {
 	pub arcs: Vec <Arc>,
 	pub lines: Vec <WideLine>,
@@ -19,11 +21,12 @@ impl PolyCapsule {
 		// A single circle is not a capsule
 		assert! (count >= 2);
 		
+		// Line i runs from point i to point i + 1, so segment indices
+		// match the points. This used to start with a second copy of
+		// the first line, which shifted every index after it.
 		let lines = {
 			let mut lines = vec! [];
 			
-			lines.push (WideLine::new (points [0], points [1], radius));
-			
 			for i in 1..count {
 				lines.push (WideLine::new (points [i - 1], points [i], radius));
 			}
@@ -78,11 +81,67 @@ impl PolyCapsule {
 	pub fn affine <F> (&self, f: F) -> PolyCapsule where F: Fn (Vec2 <Fx32>) -> Vec2 <Fx32>
 	{
 		PolyCapsule {
-			arcs: self.arcs.iter ().map (|a| Arc { circle: Circle { center: f (a.circle.center), radius: a.circle.radius }, rejected_normals: a.rejected_normals }).collect (),
-			lines: self.lines.iter ().map (|l| WideLine::new (f (l.start), f (l.end), l.radius)).collect (),
+			arcs: self.arcs.iter ().map (|a| Arc { circle: Circle { center: f (a.circle.center), radius: a.circle.radius }, rejected_normals: a.rejected_normals, one_way: a.one_way, material: a.material }).collect (),
+			// Note: one_way normals are kept as-is, so this is only
+			// correct for transforms that don't rotate
+			lines: self.lines.iter ().map (|l| WideLine {
+				one_way: l.one_way,
+				material: l.material,
+				.. WideLine::new (f (l.start), f (l.end), l.radius)
+			}).collect (),
+		}
+	}
+	
+	// Turns the whole capsule into a platform that can be jumped through
+	// from below and landed on from above. 'up' is the solid side.
+	pub fn one_way (&self, up: Vec2 <Fx32Small>) -> PolyCapsule {
+		PolyCapsule {
+			arcs: self.arcs.iter ().map (|a| Arc { one_way: Some (up), .. *a }).collect (),
+			lines: self.lines.iter ().map (|l| WideLine { one_way: Some (up), .. *l }).collect (),
 		}
 	}
 	
+	pub fn with_material (&self, material: Material) -> PolyCapsule {
+		PolyCapsule {
+			arcs: self.arcs.iter ().map (|a| Arc { material: material, .. *a }).collect (),
+			lines: self.lines.iter ().map (|l| WideLine { material: material, .. *l }).collect (),
+		}
+	}
+	
+	// Segment i runs from point i to point i + 1. Each joint takes the
+	// material of the segment starting at it, and the last endpoint takes
+	// the last segment's.
+	pub fn set_segment_material (&mut self, i: usize, material: Material) {
+		if i >= self.lines.len () {
+			return;
+		}
+		
+		self.lines [i].material = material;
+		self.arcs [i].material = material;
+		
+		if i + 1 == self.lines.len () {
+			self.arcs [i + 1].material = material;
+		}
+	}
+	
+	// Corners of an axis-aligned box around the whole capsule
+	pub fn bounds (&self) -> (Vec2 <Fx32>, Vec2 <Fx32>) {
+		let mut min = self.arcs [0].circle.center;
+		let mut max = min;
+		
+		for arc in self.arcs.iter () {
+			let c = arc.circle.center;
+			let r = arc.circle.radius;
+			
+			min.x = if c.x - r < min.x { c.x - r } else { min.x };
+			min.y = if c.y - r < min.y { c.y - r } else { min.y };
+			max.x = if c.x + r > max.x { c.x + r } else { max.x };
+			max.y = if c.y + r > max.y { c.y + r } else { max.y };
+		}
+		
+		(min, max)
+	}
+	
 	pub fn translate (&self, offset: Vec2 <Fx32>) -> PolyCapsule {
 		self.affine (|p| p + offset)
 	}
@@ -0,0 +1,231 @@
+use aabb::Aabb;
+use bake::Feature;
+use bake::Shape;
+use broadphase::Broadphase;
+use broadphase::ray_slop;
+use contact::Contact;
+use fx32::Fx32;
+use fx32::Fx32Small;
+use ray2::Ray2;
+use raytraceresult::Ray2TraceResult;
+use vec2::Vec2;
+
+use std::cmp;
+
+/*
+Raycasts for gameplay, like line of sight and lasers.
+
+The particle tracer expects rays about one tick long, so a long
+raycast is cut into steps of QUERY_STEP and traced one step at a
+time. That also keeps each step's broadphase lookup small.
+
+Obstacles are inflated by the particle radius when they're added.
+shrink pulls them back in before tracing, so passing the sim's
+radius traces against the bare centerlines.
+*/
+
+// Short enough that the tracer's 32-bit products don't overflow
+const QUERY_STEP: i32 = 64;
+
+#[derive (Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RaycastHit {
+	// How far from the origin, along dir
+	pub distance: Fx32,
+	// contact.t is distance as a fraction of max_distance
+	pub contact: Contact,
+	// The origin was already inside the obstacle. distance is 0 and
+	// contact.depth says how far in it was.
+	pub inside: bool,
+}
+
+// Calls f with each step's ray and how far along the query it starts,
+// until f returns false
+fn for_each_step <F> (origin: Vec2 <Fx32>, dir: Vec2 <Fx32>, max_distance: Fx32, mut f: F)
+where F: FnMut (&Ray2, Fx32) -> bool
+{
+	let dir: Vec2 <Fx32> = dir.normalized_64 ().into ();
+	let step = Fx32::from_int (QUERY_STEP);
+	
+	let mut traveled = Fx32::from_int (0);
+	
+	while traveled < max_distance {
+		let length = Fx32 { x: cmp::min (step.x, (max_distance - traveled).x) };
+		let ray = Ray2::new (origin + dir.scale_64 (traveled), dir.scale_64 (length));
+		
+		if ! f (&ray, traveled) {
+			return;
+		}
+		
+		traveled = traveled + length;
+	}
+}
+
+fn to_hit (result: &Ray2TraceResult, ray: &Ray2, traveled: Fx32, max_distance: Fx32) -> Option <RaycastHit> {
+	let (c, inside) = match *result {
+		Ray2TraceResult::Miss => return None,
+		Ray2TraceResult::Hit (c) => (c, false),
+		// A pop further along means rounding lost the hit at the end
+		// of the last step, so it counts as a hit where this one starts
+		Ray2TraceResult::Pop (c) => (c, traveled == Fx32::from_int (0)),
+	};
+	
+	let distance = traveled + ray.get_length ().mul_64 (c.t.into ());
+	let distance = Fx32 { x: cmp::min (distance.x, max_distance.x) };
+	
+	Some (RaycastHit {
+		distance: distance,
+		contact: Contact {
+			t: distance.div_64 (max_distance).to_small (),
+			.. c
+		},
+		inside: inside,
+	})
+}
+
+// The first thing along the ray, if anything. dir doesn't need to
+// be normalized.
+pub fn raycast (world: &Broadphase, origin: Vec2 <Fx32>, dir: Vec2 <Fx32>, max_distance: Fx32, shrink: Fx32) -> Option <RaycastHit> {
+	let mut scratch = vec! [];
+	let mut hit = None;
+	
+	for_each_step (origin, dir, max_distance, |ray, traveled| {
+		let result = world.trace_shrunk_in (ray, shrink, &mut scratch, |r| r);
+		
+		hit = to_hit (&result, ray, traveled, max_distance);
+		
+		hit.is_none ()
+	});
+	
+	hit
+}
+
+// Every obstacle along the ray, nearest first, with ties in the order
+// the obstacles were added. Each obstacle is only reported where the
+// ray first enters it.
+pub fn raycast_all (world: &Broadphase, origin: Vec2 <Fx32>, dir: Vec2 <Fx32>, max_distance: Fx32, shrink: Fx32, out: &mut Vec <RaycastHit>) {
+	let mut scratch = vec! [];
+	out.clear ();
+	
+	let obstacle = |h: &RaycastHit| h.contact.feature.map (|f| f.obstacle).unwrap_or (0);
+	
+	for_each_step (origin, dir, max_distance, |ray, traveled| {
+		world.trace_each_shrunk (ray, shrink, &mut scratch, |result| {
+			if let Some (hit) = to_hit (&result, ray, traveled, max_distance) {
+				out.push (hit);
+			}
+		});
+		
+		true
+	});
+	
+	// Keep each obstacle's nearest hit. The sort is stable, so on a tie
+	// the one found first wins.
+	out.sort_by_key (|h| (obstacle (h), h.distance.x));
+	out.dedup_by_key (|h| obstacle (h));
+	
+	out.sort_by_key (|h| (h.distance.x, obstacle (h)));
+}
+
+#[derive (Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Sweep {
+	// Moved the whole way without touching anything
+	Clear,
+	// Stopped part way. contact.t is the fraction of the motion, and
+	// contact.pos is where the center stops.
+	Hit (Contact),
+	// Already overlapping something at the start, so it can't move.
+	// contact.depth and contact.normal say how to push it out.
+	Overlap (Contact),
+}
+
+// How far p is inside a feature, if it's inside. One-way features
+// are skipped, since particles are allowed to pass through them.
+fn overlap (feature: &Feature, p: Vec2 <Fx32>) -> Option <Contact> {
+	let (depth, normal, point, radius, material) = match feature.shape {
+		Shape::Arc (ref baked) => {
+			let arc = &baked.arc;
+			if arc.one_way.is_some () {
+				return None;
+			}
+			
+			let diff = p - arc.circle.center;
+			let distance = diff.length_64 ();
+			let normal = if distance > 0 {
+				diff.normalized_64 ()
+			}
+			else {
+				// Dead center, so any way out is as good as another
+				Vec2::<Fx32Small> {
+					x: Fx32::from_int (0).to_small (),
+					y: Fx32::from_int (-1).to_small (),
+				}
+			};
+			
+			(arc.circle.radius - distance, normal, arc.circle.center, arc.circle.radius, arc.material)
+		},
+		Shape::Line (ref line) => {
+			if line.one_way.is_some () {
+				return None;
+			}
+			
+			let diff = p - line.start;
+			let along = diff.dot_64 (line.tangent.into ());
+			if along < 0 || along > line.length {
+				// The arcs at the ends cover this
+				return None;
+			}
+			
+			let sdf = diff.dot_64 (line.normal.into ());
+			let normal = if sdf >= 0 { line.normal } else { -line.normal };
+			let point = line.start + Vec2::<Fx32>::from (line.tangent).scale_64 (along);
+			
+			(line.radius - sdf.abs (), normal, point, line.radius, line.material)
+		},
+	};
+	
+	if depth <= 0 {
+		return None;
+	}
+	
+	let pos = point + Vec2::<Fx32>::from (normal).scale_64 (radius);
+	
+	Some (Contact {
+		feature: Some (feature.id),
+		.. Contact::pop (depth, pos, point, normal, material)
+	})
+}
+
+/*
+Where a particle's center would stop if it moved by motion, without
+moving anything.
+
+shrink works like it does for raycast, so sweeping a circle of radius
+r through a world inflated by the sim's radius needs a shrink of
+(sim radius - r).
+*/
+pub fn sweep (world: &Broadphase, start: Vec2 <Fx32>, motion: Vec2 <Fx32>, shrink: Fx32) -> Sweep {
+	let mut scratch = vec! [];
+	let start_bounds = Aabb::from_points (start, start).expand (ray_slop () + Fx32 { x: cmp::max (-shrink.x, 0) });
+	
+	world.candidates (&start_bounds, &mut scratch);
+	
+	// The deepest overlap wins, same as for pops
+	let deepest = scratch.iter ()
+	.filter_map (|i| overlap (&world.features [*i as usize].shrunk (shrink), start))
+	.map (Ray2TraceResult::Pop)
+	.fold (Ray2TraceResult::Miss, Ray2TraceResult::fold);
+	
+	if let Ray2TraceResult::Pop (c) = deepest {
+		return Sweep::Overlap (c);
+	}
+	
+	let distance = motion.length_64 ();
+	
+	match raycast (world, start, motion, distance, shrink) {
+		// Rounding can put it just inside when the overlap test said it
+		// wasn't
+		Some (ref hit) if hit.inside => Sweep::Overlap (hit.contact),
+		Some (hit) => Sweep::Hit (hit.contact),
+		None => Sweep::Clear,
+	}
+}
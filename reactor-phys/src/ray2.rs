@@ -2,6 +2,7 @@ use fx32::Fx32;
 use fx32::Fx32Small;
 use vec2::Vec2;
 
+#[derive (Clone, Copy, Debug)]
 pub struct Ray2 {
 	pub start: Vec2 <Fx32>,
 	dir: Vec2 <Fx32>,
@@ -14,7 +15,18 @@ impl Ray2 {
 		Ray2 {
 			start: start,
 			dir: dir,
-			length: dir.length (),
+			// 64 bits so long rays don't overflow
+			length: dir.length_64 (),
+		}
+	}
+	
+	// For restoring snapshots. length should be what new would have
+	// worked out for dir.
+	pub fn from_parts (start: Vec2 <Fx32>, dir: Vec2 <Fx32>, length: Fx32) -> Ray2 {
+		Ray2 {
+			start: start,
+			dir: dir,
+			length: length,
 		}
 	}
 	
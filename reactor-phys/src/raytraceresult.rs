@@ -1,42 +1,137 @@
+use bake::FeatureRef;
+use contact::Contact;
 use fx32::Fx32;
-use fx32::Fx32Small;
 use vec2::Vec2;
 
-#[derive (Clone, Copy)]
+#[derive (Clone, Copy, Debug, Eq, PartialEq)]
 pub enum Ray2TraceResult {
-	Hit (Fx32Small, Vec2 <Fx32>, Vec2 <Fx32Small>),
-	Pop (Vec2 <Fx32>, Vec2 <Fx32Small>),
+	Hit (Contact),
+	Pop (Contact),
 	Miss,
 }
 
 impl Ray2TraceResult {
+	/*
+	Picks the result that matters more. Pops beat hits, deeper pops
+	beat shallower ones, and earlier hits beat later ones. Anything
+	still tied is broken by Contact::tie_key, so folding the same
+	results in any order gives the same answer.
+	*/
 	pub fn fold (a: Ray2TraceResult, b: Ray2TraceResult) -> Ray2TraceResult {
-		match a {
-			Ray2TraceResult::Miss => {
-				return b;
+		match (a, b) {
+			(Ray2TraceResult::Miss, _) => b,
+			(_, Ray2TraceResult::Miss) => a,
+			(Ray2TraceResult::Pop (..), Ray2TraceResult::Hit (..)) => a,
+			(Ray2TraceResult::Hit (..), Ray2TraceResult::Pop (..)) => b,
+			(Ray2TraceResult::Pop (a_contact), Ray2TraceResult::Pop (b_contact)) => {
+				let a_key = (-a_contact.depth.x, a_contact.tie_key ());
+				let b_key = (-b_contact.depth.x, b_contact.tie_key ());
+				
+				if a_key <= b_key { a } else { b }
 			},
-			Ray2TraceResult::Hit (a_t, ..) => {
-				match b {
-					Ray2TraceResult::Miss => {
-						return a;
-					},
-					Ray2TraceResult::Hit (b_t, ..) => {
-						if a_t.x < b_t.x {
-							return a;
-						}
-						else {
-							return b;
-						}
-					},
-					Ray2TraceResult::Pop (..) => {
-						return b;
-					}
-				}
-			},
-			Ray2TraceResult::Pop (..) => {
-				return a;
+			(Ray2TraceResult::Hit (a_contact), Ray2TraceResult::Hit (b_contact)) => {
+				let a_key = (a_contact.t.x.x, a_contact.tie_key ());
+				let b_key = (b_contact.t.x.x, b_contact.tie_key ());
+				
+				if a_key <= b_key { a } else { b }
 			},
 		}
 	}
+	
+	pub fn contact (&self) -> Option <&Contact> {
+		match *self {
+			Ray2TraceResult::Hit (ref c) => Some (c),
+			Ray2TraceResult::Pop (ref c) => Some (c),
+			Ray2TraceResult::Miss => None,
+		}
+	}
+	
+	// Tags the contact with the feature it came from
+	pub fn with_feature (self, feature: FeatureRef) -> Ray2TraceResult {
+		match self {
+			Ray2TraceResult::Hit (c) => Ray2TraceResult::Hit (Contact { feature: Some (feature), .. c }),
+			Ray2TraceResult::Pop (c) => Ray2TraceResult::Pop (Contact { feature: Some (feature), .. c }),
+			Ray2TraceResult::Miss => Ray2TraceResult::Miss,
+		}
+	}
 }
 
+/*
+Folds trace results like Ray2TraceResult::fold, but when several hits
+land at the same t, e.g. in the crease between two capsules, their
+normals are averaged so the particle bounces out of both.
+
+The normals are summed as integers, so this is order-independent too.
+*/
+#[derive (Clone, Copy, Debug)]
+pub struct Resolver {
+	best: Ray2TraceResult,
+	// Sum of the normals of every hit at best's t
+	normal_sum: Vec2 <Fx32>,
+	hit_count: u32,
+}
+
+impl Resolver {
+	pub fn new () -> Resolver {
+		Resolver {
+			best: Ray2TraceResult::Miss,
+			normal_sum: Vec2::<Fx32> {
+				x: Fx32::from_int (0),
+				y: Fx32::from_int (0),
+			},
+			hit_count: 0,
+		}
+	}
+	
+	pub fn add (self, r: Ray2TraceResult) -> Resolver {
+		let best = Ray2TraceResult::fold (self.best, r);
+		
+		let (best_t, t, normal) = match (best, r) {
+			(Ray2TraceResult::Hit (best_contact), Ray2TraceResult::Hit (c)) => (best_contact.t, c.t, c.normal),
+			_ => return Resolver { best: best, .. self },
+		};
+		
+		if t != best_t {
+			return Resolver { best: best, .. self };
+		}
+		
+		let earlier = match self.best {
+			Ray2TraceResult::Hit (old) => old.t != t,
+			_ => true,
+		};
+		
+		if earlier {
+			// r is the first hit at this t
+			Resolver {
+				best: best,
+				normal_sum: normal.into (),
+				hit_count: 1,
+			}
+		}
+		else {
+			Resolver {
+				best: best,
+				normal_sum: self.normal_sum + normal.into (),
+				hit_count: self.hit_count + 1,
+			}
+		}
+	}
+	
+	pub fn finish (self) -> Ray2TraceResult {
+		match self.best {
+			Ray2TraceResult::Hit (c) if self.hit_count > 1 => {
+				// Opposite normals cancel out, and then there's no
+				// good answer, so keep the one we picked
+				if self.normal_sum.length_64 () < Fx32::from_q (1, 16) {
+					return self.best;
+				}
+				
+				Ray2TraceResult::Hit (Contact {
+					normal: self.normal_sum.normalized_64 (),
+					.. c
+				})
+			},
+			_ => self.best,
+		}
+	}
+}
@@ -0,0 +1,411 @@
+use broadphase::Broadphase;
+use config::PhysicsConfig;
+use fx32::Fx32;
+use fx32::FRACTIONAL_BITS;
+use material::Material;
+use polycapsule::PolyCapsule;
+use ray2::Ray2;
+use raytrace::write_vec2;
+use raytraceresult::Ray2TraceResult;
+use solver::step_particle;
+use stats::StepStats;
+use vec2::Vec2;
+
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+
+/*
+A level, some particles and the physics settings to drop them with,
+loaded from a text file so tests and tools don't have to hardcode
+them.
+
+One directive per line. Blank lines and anything after a # are
+ignored. Numbers can be integers, decimals or fractions like 1/8,
+and have to fit in an Fx32, so stay within about 32767 either way.
+
+	gravity x y
+	subticks n
+	contact_speed s
+	sleep_speed s
+	sleep_ticks n
+	restitution r             Multiplies every hit's restitution
+	pop_restitution r
+	max_speed s
+	ticks n                   How many ticks to run each particle for
+	despawn_below y           Stop a particle once it falls past y
+	radius r                  For the capsules after it
+	material r sf df mbs      For the capsules after it. Restitution,
+	                          static and dynamic friction, and min
+	                          bounce speed
+	particle_material r sf df mbs
+	capsule x y x y ...
+	one_way_capsule ux uy x y x y ...
+	spawn x y vx vy
+	spawn_row x y dx count vx vy
+	                          count particles starting at (x, y), dx
+	                          apart, at most MAX_SPAWN_ROW of them
+*/
+
+#[derive (Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Spawn {
+	pub pos: Vec2 <Fx32>,
+	pub vel: Vec2 <Fx32>,
+}
+
+pub struct Scenario {
+	pub obstacles: Vec <PolyCapsule>,
+	pub spawns: Vec <Spawn>,
+	pub config: PhysicsConfig,
+	pub particle_material: Material,
+	pub ticks: u32,
+	pub despawn_below: Option <Fx32>,
+}
+
+#[derive (Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TrajectoryPoint {
+	pub pos: Vec2 <Fx32>,
+	// Ticks since the particle spawned
+	pub clock: Fx32,
+	// The trace that moved it here. Miss for the spawn point.
+	pub result: Ray2TraceResult,
+}
+
+pub struct ScenarioResult {
+	// Added up over every particle's every tick, like
+	// CherenkovSim::stats. traces counts every point in the
+	// trajectories after the first.
+	pub stats: StepStats,
+	// One per spawn, in the same order
+	pub trajectories: Vec <Vec <TrajectoryPoint>>,
+}
+
+#[derive (Debug)]
+pub enum ScenarioError {
+	Io (io::Error),
+	// Line numbers start at 1
+	Parse (usize, String),
+}
+
+impl fmt::Display for ScenarioError {
+	fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			ScenarioError::Io (ref e) => write! (f, "{}", e),
+			ScenarioError::Parse (line, ref message) => write! (f, "line {}: {}", line, message),
+		}
+	}
+}
+
+impl From <io::Error> for ScenarioError {
+	fn from (e: io::Error) -> ScenarioError {
+		ScenarioError::Io (e)
+	}
+}
+
+// spawn_row makes this many particles at most
+pub const MAX_SPAWN_ROW: u32 = 4096;
+
+// Raw Fx32 bits worked out in 64 bits, so anything too big is caught
+// here instead of wrapping
+fn fx32_from_raw (raw: i64) -> Option <Fx32> {
+	if raw < i32::min_value () as i64 || raw > i32::max_value () as i64 {
+		None
+	}
+	else {
+		Some (Fx32::new (raw as i32))
+	}
+}
+
+fn parse_fx32 (s: &str) -> Result <Fx32, &'static str> {
+	let raw = if let Some (slash) = s.find ('/') {
+		match (s [..slash].parse::<i64> (), s [slash + 1..].parse::<i64> ()) {
+			(Ok (_), Ok (0)) => return Err ("divides by zero"),
+			(Ok (num), Ok (den)) => num.checked_mul (1 << FRACTIONAL_BITS).map (|n| n / den),
+			_ => return Err ("isn't a number"),
+		}
+	}
+	else if let Ok (i) = s.parse::<i64> () {
+		i.checked_mul (1 << FRACTIONAL_BITS)
+	}
+	else {
+		// Rounded to the nearest bit, so it's the same on every machine
+		match s.parse::<f64> () {
+			Ok (f) => {
+				let f = (f * Fx32::from_int (1).x as f64).round ();
+				
+				// Also catches NaN and infinity
+				if f >= i32::min_value () as f64 && f <= i32::max_value () as f64 {
+					Some (f as i64)
+				}
+				else {
+					None
+				}
+			},
+			Err (_) => return Err ("isn't a number"),
+		}
+	};
+	
+	match raw.and_then (fx32_from_raw) {
+		Some (x) => Ok (x),
+		None => Err ("is out of range"),
+	}
+}
+
+fn parse_material (args: &[Fx32]) -> Option <Material> {
+	// Not abs, which overflows on the most negative Fx32
+	if args [..3].iter ().any (|x| *x <= -2 || *x >= 2) {
+		return None;
+	}
+	
+	Some (Material {
+		restitution: args [0].to_small (),
+		static_friction: args [1].to_small (),
+		dynamic_friction: args [2].to_small (),
+		min_bounce_speed: args [3],
+	})
+}
+
+fn points (args: &[Fx32]) -> Vec <Vec2 <Fx32>> {
+	args.chunks (2).map (|p| Vec2::<Fx32> { x: p [0], y: p [1] }).collect ()
+}
+
+impl Scenario {
+	pub fn load (filename: &str) -> Result <Scenario, ScenarioError> {
+		let mut text = String::new ();
+		File::open (filename)?.read_to_string (&mut text)?;
+		
+		Scenario::parse (&text)
+	}
+	
+	pub fn parse (text: &str) -> Result <Scenario, ScenarioError> {
+		let mut scenario = Scenario {
+			obstacles: vec! [],
+			spawns: vec! [],
+			config: PhysicsConfig::default (),
+			particle_material: Material::neutral (),
+			ticks: 200,
+			despawn_below: None,
+		};
+		
+		let mut radius = Fx32::from_int (8);
+		let mut material = Material::default ();
+		
+		for (i, line) in text.lines ().enumerate () {
+			let line_number = i + 1;
+			let error = |message: &str| ScenarioError::Parse (line_number, message.to_string ());
+			
+			let line = match line.find ('#') {
+				Some (comment) => &line [..comment],
+				None => line,
+			};
+			let mut words = line.split_whitespace ();
+			
+			let directive = match words.next () {
+				Some (d) => d,
+				None => continue,
+			};
+			
+			let mut args = vec! [];
+			for word in words {
+				match parse_fx32 (word) {
+					Ok (x) => args.push (x),
+					Err (problem) => return Err (error (&format! ("{} {}", word, problem))),
+				}
+			}
+			
+			let expect = |n: usize| if args.len () == n {
+				Ok (())
+			}
+			else {
+				Err (error (&format! ("{} takes {} numbers", directive, n)))
+			};
+			
+			// Counts and tick numbers
+			let whole = |x: Fx32| if x.x >= 0 && x.x & (Fx32::from_int (1).x - 1) == 0 {
+				Ok (x.to_i32 () as u32)
+			}
+			else {
+				Err (error (&format! ("{} needs a whole number", directive)))
+			};
+			
+			let unit = |x: Fx32| if x >= 0 && x <= 1 {
+				Ok (x.to_small ())
+			}
+			else {
+				Err (error (&format! ("{} needs a number from 0 to 1", directive)))
+			};
+			
+			match directive {
+				"gravity" => {
+					expect (2)?;
+					scenario.config.gravity = Vec2::<Fx32> { x: args [0], y: args [1] };
+				},
+				"subticks" => {
+					expect (1)?;
+					scenario.config.subticks = whole (args [0])?;
+				},
+				"contact_speed" => {
+					expect (1)?;
+					scenario.config.contact_speed = args [0];
+				},
+				"sleep_speed" => {
+					expect (1)?;
+					scenario.config.sleep_speed = args [0];
+				},
+				"sleep_ticks" => {
+					expect (1)?;
+					scenario.config.sleep_ticks = whole (args [0])?;
+				},
+				"restitution" => {
+					expect (1)?;
+					scenario.config.restitution = unit (args [0])?;
+				},
+				"pop_restitution" => {
+					expect (1)?;
+					scenario.config.pop_restitution = unit (args [0])?;
+				},
+				"max_speed" => {
+					expect (1)?;
+					scenario.config.max_speed = Some (args [0]);
+				},
+				"ticks" => {
+					expect (1)?;
+					scenario.ticks = whole (args [0])?;
+				},
+				"despawn_below" => {
+					expect (1)?;
+					scenario.despawn_below = Some (args [0]);
+				},
+				"radius" => {
+					expect (1)?;
+					radius = args [0];
+				},
+				"material" => {
+					expect (4)?;
+					material = parse_material (&args).ok_or_else (|| error ("material needs restitution and friction under 2"))?;
+				},
+				"particle_material" => {
+					expect (4)?;
+					scenario.particle_material = parse_material (&args).ok_or_else (|| error ("material needs restitution and friction under 2"))?;
+				},
+				"capsule" => {
+					if args.len () < 4 || args.len () % 2 != 0 {
+						return Err (error ("capsule needs at least 2 points"));
+					}
+					
+					let capsule = PolyCapsule::new (&points (&args), radius).with_material (material);
+					scenario.obstacles.push (capsule);
+				},
+				"one_way_capsule" => {
+					if args.len () < 6 || args.len () % 2 != 0 {
+						return Err (error ("one_way_capsule needs an up direction and at least 2 points"));
+					}
+					
+					let up = Vec2::<Fx32> { x: args [0], y: args [1] }.normalized_64 ();
+					let capsule = PolyCapsule::new (&points (&args [2..]), radius).with_material (material).one_way (up);
+					scenario.obstacles.push (capsule);
+				},
+				"spawn" => {
+					expect (4)?;
+					scenario.spawns.push (Spawn {
+						pos: Vec2::<Fx32> { x: args [0], y: args [1] },
+						vel: Vec2::<Fx32> { x: args [2], y: args [3] },
+					});
+				},
+				"spawn_row" => {
+					expect (6)?;
+					
+					let count = whole (args [3])?;
+					if count > MAX_SPAWN_ROW {
+						return Err (error (&format! ("spawn_row makes at most {} particles", MAX_SPAWN_ROW)));
+					}
+					
+					for j in 0..count {
+						let x = fx32_from_raw (args [0].x as i64 + args [2].x as i64 * j as i64)
+						.ok_or_else (|| error ("spawn_row goes out of range"))?;
+						
+						scenario.spawns.push (Spawn {
+							pos: Vec2::<Fx32> { x: x, y: args [1] },
+							vel: Vec2::<Fx32> { x: args [4], y: args [5] },
+						});
+					}
+				},
+				_ => return Err (error (&format! ("unknown directive {}", directive))),
+			}
+		}
+		
+		Ok (scenario)
+	}
+	
+	pub fn run (&self) -> ScenarioResult {
+		let world = Broadphase::new (&self.obstacles);
+		let mut scratch = vec! [];
+		let mut stats = StepStats::default ();
+		let mut trajectories = vec! [];
+		
+		for spawn in self.spawns.iter () {
+			let mut particle = Ray2::new (spawn.pos, spawn.vel);
+			let mut clock = Fx32::from_int (0);
+			let mut trajectory = vec! [TrajectoryPoint {
+				pos: particle.start,
+				clock: clock,
+				result: Ray2TraceResult::Miss,
+			}];
+			
+			for _ in 0..self.ticks {
+				let mut tick_stats = StepStats::default ();
+				
+				let remaining_dt = step_particle (&world, &mut scratch, &self.config, &mut particle, &self.particle_material, Fx32::from_int (0), Fx32::from_int (1), |p, tick_clock, _, trace_result| {
+					trajectory.push (TrajectoryPoint {
+						pos: p.start,
+						clock: clock + tick_clock,
+						result: *trace_result,
+					});
+					tick_stats.record (trace_result);
+				});
+				
+				clock = clock + Fx32::from_int (1) - remaining_dt;
+				
+				tick_stats.finish_particle (remaining_dt);
+				stats = stats.add (tick_stats);
+				
+				if let Some (y) = self.despawn_below {
+					if particle.start.y > y {
+						break;
+					}
+				}
+			}
+			
+			trajectories.push (trajectory);
+		}
+		
+		ScenarioResult {
+			stats: stats,
+			trajectories: trajectories,
+		}
+	}
+}
+
+impl ScenarioResult {
+	// Each trajectory as a polyline in a Wavefront OBJ, with the clock
+	// as the Z coordinate
+	pub fn write_obj <T> (&self, writer: &mut T) -> io::Result <()> where T: Write {
+		let mut vertex_i = 1;
+		
+		for trajectory in self.trajectories.iter () {
+			let polyline_start = vertex_i;
+			
+			for point in trajectory.iter () {
+				write_vec2 (writer, &point.pos, point.clock);
+				vertex_i += 1;
+			}
+			
+			for i in polyline_start..vertex_i - 1 {
+				write! (writer, "f {} {}\n", i, i + 1)?;
+			}
+		}
+		
+		Ok (())
+	}
+}
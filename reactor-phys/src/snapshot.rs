@@ -0,0 +1,512 @@
+use arc::Arc;
+use bake::FeatureKind;
+use bake::FeatureRef;
+use cherenkov::CherenkovSim;
+use circle::Circle;
+use config::PhysicsConfig;
+use contact::Contact;
+use fx32::Fx32;
+use fx32::Fx32Small;
+use material::Material;
+use particle::Particle;
+use polycapsule::PolyCapsule;
+use ray2::Ray2;
+use raytraceresult::Ray2TraceResult;
+use vec2::Vec2;
+use wide_line::WideLine;
+use world::World;
+
+use byteorder::LittleEndian;
+use byteorder::ReadBytesExt;
+use byteorder::WriteBytesExt;
+
+use std::fmt;
+use std::io;
+use std::io::prelude::*;
+use std::sync;
+
+/*
+Saves and restores everything a sim needs to carry on stepping
+exactly as if it never stopped: the obstacles, the config, and every
+particle down to the last bit.
+
+The broadphase isn't saved, since it's rebuilt from the obstacles
+with the same result. Stats and events aren't either, a restored sim
+starts with none.
+
+read_snapshot gives the restored sim its own copy of the obstacles.
+To keep sharing a World with other sims, restore with
+read_snapshot_in_world instead, which checks the snapshot's obstacles
+match it bit for bit.
+
+Everything is little-endian. Fx32s are written as their raw bits, so
+a snapshot restores the same on any machine.
+*/
+
+const MAGIC: &'static [u8; 4] = b"CHER";
+
+// Bump this whenever the layout changes. Old snapshots are rejected
+// rather than misread.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+#[derive (Debug)]
+pub enum SnapshotError {
+	// Includes running out of data partway through
+	Io (io::Error),
+	NotASnapshot,
+	Version (u32),
+	Invalid (&'static str),
+	// From read_snapshot_in_world, if the obstacles don't match
+	DifferentWorld,
+}
+
+impl fmt::Display for SnapshotError {
+	fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			SnapshotError::Io (ref e) => write! (f, "{}", e),
+			SnapshotError::NotASnapshot => write! (f, "not a snapshot"),
+			SnapshotError::Version (v) => write! (f, "snapshot version {}, expected {}", v, SNAPSHOT_VERSION),
+			SnapshotError::Invalid (message) => write! (f, "invalid snapshot: {}", message),
+			SnapshotError::DifferentWorld => write! (f, "snapshot has different obstacles"),
+		}
+	}
+}
+
+impl From <io::Error> for SnapshotError {
+	fn from (e: io::Error) -> SnapshotError {
+		SnapshotError::Io (e)
+	}
+}
+
+pub fn write_snapshot <T> (writer: &mut T, sim: &CherenkovSim) -> io::Result <()> where T: Write {
+	writer.write_all (MAGIC)?;
+	writer.write_u32::<LittleEndian> (SNAPSHOT_VERSION)?;
+	
+	write_world (writer, &sim.world)?;
+	write_state (writer, sim)
+}
+
+// The obstacles, which don't change as often as the rest
+pub fn write_world <T> (writer: &mut T, world: &World) -> io::Result <()> where T: Write {
+	write_fx (writer, world.radius)?;
+	writer.write_u32::<LittleEndian> (world.obstacles.len () as u32)?;
+	for capsule in world.obstacles.iter () {
+		write_capsule (writer, capsule)?;
+	}
+	
+	Ok (())
+}
+
+// Everything but the obstacles
+pub fn write_state <T> (writer: &mut T, sim: &CherenkovSim) -> io::Result <()> where T: Write {
+	write_config (writer, &sim.config)?;
+	
+	writer.write_u32::<LittleEndian> (sim.next_handle)?;
+	writer.write_u32::<LittleEndian> (sim.particles.len () as u32)?;
+	for p in sim.particles.iter () {
+		write_particle (writer, p)?;
+	}
+	
+	Ok (())
+}
+
+pub fn read_snapshot <T> (reader: &mut T) -> Result <CherenkovSim, SnapshotError> where T: Read {
+	read_header (reader)?;
+	let world = read_world (reader)?;
+	
+	read_state (reader, sync::Arc::new (world))
+}
+
+// Same as read_snapshot, but the sim shares world instead of getting
+// its own copy. Build world first, like for CherenkovSim::new.
+pub fn read_snapshot_in_world <T> (reader: &mut T, world: sync::Arc <World>) -> Result <CherenkovSim, SnapshotError> where T: Read {
+	read_header (reader)?;
+	
+	if ! same_world (&read_world (reader)?, &world) {
+		return Err (SnapshotError::DifferentWorld);
+	}
+	
+	read_state (reader, world)
+}
+
+// Compares what they'd write, so only the bits that get saved count
+fn same_world (a: &World, b: &World) -> bool {
+	let mut a_bytes = vec! [];
+	let mut b_bytes = vec! [];
+	
+	// Writing to a Vec never fails
+	write_world (&mut a_bytes, a).unwrap ();
+	write_world (&mut b_bytes, b).unwrap ();
+	
+	a_bytes == b_bytes
+}
+
+fn read_header <T> (reader: &mut T) -> Result <(), SnapshotError> where T: Read {
+	let mut magic = [0u8; 4];
+	reader.read_exact (&mut magic)?;
+	if &magic != MAGIC {
+		return Err (SnapshotError::NotASnapshot);
+	}
+	
+	let version = reader.read_u32::<LittleEndian> ()?;
+	if version != SNAPSHOT_VERSION {
+		return Err (SnapshotError::Version (version));
+	}
+	
+	Ok (())
+}
+
+fn read_world <T> (reader: &mut T) -> Result <World, SnapshotError> where T: Read {
+	let mut world = World::new (read_fx (reader)?);
+	for _ in 0..reader.read_u32::<LittleEndian> ()? {
+		world.add_obstacle (read_capsule (reader)?);
+	}
+	
+	Ok (world)
+}
+
+fn read_state <T> (reader: &mut T, world: sync::Arc <World>) -> Result <CherenkovSim, SnapshotError> where T: Read {
+	let mut sim = CherenkovSim::new (world);
+	sim.config = read_config (reader)?;
+	
+	sim.next_handle = reader.read_u32::<LittleEndian> ()?;
+	for _ in 0..reader.read_u32::<LittleEndian> ()? {
+		let p = read_particle (reader)?;
+		
+		// Handles have to stay sorted for CherenkovSim::particle
+		let sorted = match sim.particles.last () {
+			Some (last) => p.handle > last.handle,
+			None => p.handle == 0,
+		};
+		if ! sorted || p.handle >= sim.next_handle {
+			return Err (SnapshotError::Invalid ("particle handles out of order"));
+		}
+		
+		sim.particles.push (p);
+	}
+	
+	if sim.particles.is_empty () {
+		return Err (SnapshotError::Invalid ("no player"));
+	}
+	
+	let mut rest = [0u8; 1];
+	if reader.read (&mut rest)? != 0 {
+		return Err (SnapshotError::Invalid ("trailing data"));
+	}
+	
+	Ok (sim)
+}
+
+fn write_fx <T> (writer: &mut T, x: Fx32) -> io::Result <()> where T: Write {
+	writer.write_i32::<LittleEndian> (x.x)
+}
+
+fn write_small <T> (writer: &mut T, x: Fx32Small) -> io::Result <()> where T: Write {
+	write_fx (writer, x.x)
+}
+
+fn write_vec <T> (writer: &mut T, v: Vec2 <Fx32>) -> io::Result <()> where T: Write {
+	write_fx (writer, v.x)?;
+	write_fx (writer, v.y)
+}
+
+fn write_small_vec <T> (writer: &mut T, v: Vec2 <Fx32Small>) -> io::Result <()> where T: Write {
+	write_small (writer, v.x)?;
+	write_small (writer, v.y)
+}
+
+fn write_bool <T> (writer: &mut T, b: bool) -> io::Result <()> where T: Write {
+	writer.write_u8 (if b { 1 } else { 0 })
+}
+
+fn write_opt_normal <T> (writer: &mut T, normal: Option <Vec2 <Fx32Small>>) -> io::Result <()> where T: Write {
+	write_bool (writer, normal.is_some ())?;
+	match normal {
+		Some (n) => write_small_vec (writer, n),
+		None => Ok (()),
+	}
+}
+
+fn write_material <T> (writer: &mut T, m: &Material) -> io::Result <()> where T: Write {
+	write_small (writer, m.restitution)?;
+	write_small (writer, m.static_friction)?;
+	write_small (writer, m.dynamic_friction)?;
+	write_fx (writer, m.min_bounce_speed)
+}
+
+fn write_capsule <T> (writer: &mut T, capsule: &PolyCapsule) -> io::Result <()> where T: Write {
+	writer.write_u32::<LittleEndian> (capsule.arcs.len () as u32)?;
+	for arc in capsule.arcs.iter () {
+		write_vec (writer, arc.circle.center)?;
+		write_fx (writer, arc.circle.radius)?;
+		write_small_vec (writer, arc.rejected_normals [0])?;
+		write_small_vec (writer, arc.rejected_normals [1])?;
+		write_opt_normal (writer, arc.one_way)?;
+		write_material (writer, &arc.material)?;
+	}
+	
+	writer.write_u32::<LittleEndian> (capsule.lines.len () as u32)?;
+	for line in capsule.lines.iter () {
+		write_vec (writer, line.start)?;
+		write_vec (writer, line.end)?;
+		write_fx (writer, line.radius)?;
+		write_small_vec (writer, line.line_tangent)?;
+		write_opt_normal (writer, line.one_way)?;
+		write_material (writer, &line.material)?;
+	}
+	
+	Ok (())
+}
+
+fn write_config <T> (writer: &mut T, config: &PhysicsConfig) -> io::Result <()> where T: Write {
+	write_vec (writer, config.gravity)?;
+	writer.write_u32::<LittleEndian> (config.subticks)?;
+	write_fx (writer, config.contact_speed)?;
+	write_fx (writer, config.sleep_speed)?;
+	writer.write_u32::<LittleEndian> (config.sleep_ticks)?;
+	write_small (writer, config.restitution)?;
+	write_small (writer, config.pop_restitution)?;
+	
+	write_bool (writer, config.max_speed.is_some ())?;
+	match config.max_speed {
+		Some (max_speed) => write_fx (writer, max_speed),
+		None => Ok (()),
+	}
+}
+
+fn write_result <T> (writer: &mut T, result: &Ray2TraceResult) -> io::Result <()> where T: Write {
+	let (kind, c) = match *result {
+		Ray2TraceResult::Miss => return writer.write_u8 (0),
+		Ray2TraceResult::Hit (ref c) => (1, c),
+		Ray2TraceResult::Pop (ref c) => (2, c),
+	};
+	writer.write_u8 (kind)?;
+	
+	write_bool (writer, c.feature.is_some ())?;
+	if let Some (f) = c.feature {
+		writer.write_u32::<LittleEndian> (f.obstacle)?;
+		writer.write_u8 (match f.kind {
+			FeatureKind::Arc => 0,
+			FeatureKind::Line => 1,
+		})?;
+		writer.write_u32::<LittleEndian> (f.index)?;
+	}
+	
+	write_small (writer, c.t)?;
+	write_fx (writer, c.depth)?;
+	write_vec (writer, c.pos)?;
+	write_vec (writer, c.point)?;
+	write_small_vec (writer, c.normal)?;
+	write_material (writer, &c.material)
+}
+
+fn write_particle <T> (writer: &mut T, p: &Particle) -> io::Result <()> where T: Write {
+	writer.write_u32::<LittleEndian> (p.handle)?;
+	write_vec (writer, p.ray.start)?;
+	write_vec (writer, p.ray.get_dir ())?;
+	write_fx (writer, p.ray.get_length ())?;
+	write_fx (writer, p.radius)?;
+	write_material (writer, &p.material)?;
+	writer.write_u32::<LittleEndian> (p.flags)?;
+	write_bool (writer, p.sleeping)?;
+	write_fx (writer, p.still_time)?;
+	write_result (writer, &p.contact)?;
+	write_vec (writer, p.force)?;
+	write_opt_normal (writer, p.ground_normal)?;
+	write_fx (writer, p.air_time)
+}
+
+fn read_fx <T> (reader: &mut T) -> io::Result <Fx32> where T: Read {
+	Ok (Fx32 { x: reader.read_i32::<LittleEndian> ()? })
+}
+
+// Checked like Fx32::to_small does, since the data may not have come
+// from a sim at all
+fn read_small <T> (reader: &mut T) -> Result <Fx32Small, SnapshotError> where T: Read {
+	let x = read_fx (reader)?;
+	
+	if x <= -2 || x >= 2 {
+		return Err (SnapshotError::Invalid ("small number out of range"));
+	}
+	
+	Ok (Fx32Small { x: x })
+}
+
+fn read_vec <T> (reader: &mut T) -> io::Result <Vec2 <Fx32>> where T: Read {
+	Ok (Vec2::<Fx32> {
+		x: read_fx (reader)?,
+		y: read_fx (reader)?,
+	})
+}
+
+fn read_small_vec <T> (reader: &mut T) -> Result <Vec2 <Fx32Small>, SnapshotError> where T: Read {
+	Ok (Vec2::<Fx32Small> {
+		x: read_small (reader)?,
+		y: read_small (reader)?,
+	})
+}
+
+fn read_bool <T> (reader: &mut T) -> Result <bool, SnapshotError> where T: Read {
+	match reader.read_u8 ()? {
+		0 => Ok (false),
+		1 => Ok (true),
+		_ => Err (SnapshotError::Invalid ("bad bool")),
+	}
+}
+
+fn read_opt_normal <T> (reader: &mut T) -> Result <Option <Vec2 <Fx32Small>>, SnapshotError> where T: Read {
+	if read_bool (reader)? {
+		Ok (Some (read_small_vec (reader)?))
+	}
+	else {
+		Ok (None)
+	}
+}
+
+fn read_material <T> (reader: &mut T) -> Result <Material, SnapshotError> where T: Read {
+	Ok (Material {
+		restitution: read_small (reader)?,
+		static_friction: read_small (reader)?,
+		dynamic_friction: read_small (reader)?,
+		min_bounce_speed: read_fx (reader)?,
+	})
+}
+
+fn read_capsule <T> (reader: &mut T) -> Result <PolyCapsule, SnapshotError> where T: Read {
+	let mut capsule = PolyCapsule {
+		arcs: vec! [],
+		lines: vec! [],
+	};
+	
+	let num_arcs = reader.read_u32::<LittleEndian> ()?;
+	for _ in 0..num_arcs {
+		capsule.arcs.push (Arc {
+			circle: Circle {
+				center: read_vec (reader)?,
+				radius: read_fx (reader)?,
+			},
+			rejected_normals: [read_small_vec (reader)?, read_small_vec (reader)?],
+			one_way: read_opt_normal (reader)?,
+			material: read_material (reader)?,
+		});
+	}
+	
+	// PolyCapsule::new always makes one more arc than lines, and the
+	// rest of the sim indexes them assuming that
+	let num_lines = reader.read_u32::<LittleEndian> ()?;
+	if num_lines < 1 || num_arcs as u64 != num_lines as u64 + 1 {
+		return Err (SnapshotError::Invalid ("capsule needs one more arc than lines"));
+	}
+	
+	for _ in 0..num_lines {
+		capsule.lines.push (WideLine {
+			start: read_vec (reader)?,
+			end: read_vec (reader)?,
+			radius: read_fx (reader)?,
+			line_tangent: read_small_vec (reader)?,
+			one_way: read_opt_normal (reader)?,
+			material: read_material (reader)?,
+		});
+	}
+	
+	Ok (capsule)
+}
+
+fn read_config <T> (reader: &mut T) -> Result <PhysicsConfig, SnapshotError> where T: Read {
+	let config = PhysicsConfig {
+		gravity: read_vec (reader)?,
+		subticks: reader.read_u32::<LittleEndian> ()?,
+		contact_speed: read_fx (reader)?,
+		sleep_speed: read_fx (reader)?,
+		sleep_ticks: reader.read_u32::<LittleEndian> ()?,
+		restitution: read_small (reader)?,
+		pop_restitution: read_small (reader)?,
+		max_speed: if read_bool (reader)? {
+			Some (read_fx (reader)?)
+		}
+		else {
+			None
+		},
+	};
+	
+	// The same limits the cher_set_* functions keep to
+	if config.subticks < 1 {
+		return Err (SnapshotError::Invalid ("subticks must be at least 1"));
+	}
+	if ! is_unit (config.restitution) || ! is_unit (config.pop_restitution) {
+		return Err (SnapshotError::Invalid ("restitution must be from 0 to 1"));
+	}
+	if let Some (max_speed) = config.max_speed {
+		if max_speed <= 0 {
+			return Err (SnapshotError::Invalid ("max_speed must be positive"));
+		}
+	}
+	
+	Ok (config)
+}
+
+fn is_unit (x: Fx32Small) -> bool {
+	x.x >= 0 && x.x <= 1
+}
+
+fn read_result <T> (reader: &mut T) -> Result <Ray2TraceResult, SnapshotError> where T: Read {
+	let kind = reader.read_u8 ()?;
+	match kind {
+		0 => return Ok (Ray2TraceResult::Miss),
+		1 | 2 => (),
+		_ => return Err (SnapshotError::Invalid ("bad trace result")),
+	}
+	
+	let feature = if read_bool (reader)? {
+		Some (FeatureRef {
+			obstacle: reader.read_u32::<LittleEndian> ()?,
+			kind: match reader.read_u8 ()? {
+				0 => FeatureKind::Arc,
+				1 => FeatureKind::Line,
+				_ => return Err (SnapshotError::Invalid ("bad feature kind")),
+			},
+			index: reader.read_u32::<LittleEndian> ()?,
+		})
+	}
+	else {
+		None
+	};
+	
+	let c = Contact {
+		feature: feature,
+		t: read_small (reader)?,
+		depth: read_fx (reader)?,
+		pos: read_vec (reader)?,
+		point: read_vec (reader)?,
+		normal: read_small_vec (reader)?,
+		material: read_material (reader)?,
+	};
+	
+	if kind == 1 {
+		Ok (Ray2TraceResult::Hit (c))
+	}
+	else {
+		Ok (Ray2TraceResult::Pop (c))
+	}
+}
+
+fn read_particle <T> (reader: &mut T) -> Result <Particle, SnapshotError> where T: Read {
+	let handle = reader.read_u32::<LittleEndian> ()?;
+	let start = read_vec (reader)?;
+	let dir = read_vec (reader)?;
+	let length = read_fx (reader)?;
+	
+	Ok (Particle {
+		handle: handle,
+		ray: Ray2::from_parts (start, dir, length),
+		radius: read_fx (reader)?,
+		material: read_material (reader)?,
+		flags: reader.read_u32::<LittleEndian> ()?,
+		sleeping: read_bool (reader)?,
+		still_time: read_fx (reader)?,
+		contact: read_result (reader)?,
+		force: read_vec (reader)?,
+		ground_normal: read_opt_normal (reader)?,
+		air_time: read_fx (reader)?,
+	})
+}
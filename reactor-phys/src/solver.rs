@@ -0,0 +1,200 @@
+use broadphase::Broadphase;
+use config::PhysicsConfig;
+use fx32::Fx32;
+use fx32::Fx32Small;
+use material::Material;
+use ray2::Ray2;
+use raytraceresult::Ray2TraceResult;
+use vec2::Vec2;
+
+// Rounding slack for velocities that should be exactly along a surface
+fn speed_slop () -> Fx32 {
+	Fx32::from_q (1, 256)
+}
+
+// Normals this close together belong to the same surface, e.g.
+// two segments of a smooth curve, or a line and the arc at its end
+fn same_surface (a: Vec2 <Fx32>, b: Vec2 <Fx32>) -> bool {
+	a.dot_64 (b) > Fx32::from_q (63, 64)
+}
+
+/*
+The surfaces a particle is touching during one tick.
+
+Once the particle has lost its velocity into a surface, tracing
+against that surface again only finds zero-time hits and tiny pops,
+which burn subticks without moving anything. So those results are
+thrown out and the particle slides along the surface instead.
+
+Two normals are enough to wedge a particle into a corner in 2D.
+*/
+pub struct ContactSet {
+	normals: [Option <Vec2 <Fx32>>; 2],
+}
+
+impl ContactSet {
+	pub fn new () -> ContactSet {
+		ContactSet {
+			normals: [None, None],
+		}
+	}
+	
+	pub fn touching (&self, n: Vec2 <Fx32Small>) -> bool {
+		let n = Vec2::<Fx32>::from (n);
+		
+		self.normals.iter ().any (|c| match *c {
+			Some (c) => same_surface (c, n),
+			None => false,
+		})
+	}
+	
+	// n should be normalized
+	pub fn add (&mut self, n: Vec2 <Fx32Small>) {
+		if self.touching (n) {
+			return;
+		}
+		
+		self.normals [1] = self.normals [0];
+		self.normals [0] = Some (n.into ());
+	}
+	
+	// Removes any velocity going into a touched surface
+	pub fn constrain (&self, vel: Vec2 <Fx32>) -> Vec2 <Fx32> {
+		let mut vel = vel;
+		
+		for n in self.normals.iter ().filter_map (|n| *n) {
+			let into = vel.dot_64 (n);
+			if into < 0 {
+				vel = vel - n.scale_64 (into);
+			}
+		}
+		
+		// Sliding along the second surface may have pushed it back
+		// into the first one. Then it's stuck in a crease.
+		for n in self.normals.iter ().filter_map (|n| *n) {
+			if vel.dot_64 (n) < -speed_slop () {
+				return Vec2::<Fx32> {
+					x: Fx32::from_int (0),
+					y: Fx32::from_int (0),
+				};
+			}
+		}
+		
+		vel
+	}
+	
+	// Drops results from surfaces we're already sliding along
+	pub fn filter (&self, result: Ray2TraceResult, vel: Vec2 <Fx32>) -> Ray2TraceResult {
+		let normal = match result {
+			Ray2TraceResult::Miss => return result,
+			Ray2TraceResult::Hit (ref c) => c.normal,
+			Ray2TraceResult::Pop (ref c) => c.normal,
+		};
+		
+		if self.touching (normal) && vel.dot_64 (normal.into ()) >= -speed_slop () {
+			Ray2TraceResult::Miss
+		}
+		else {
+			result
+		}
+	}
+}
+
+fn clamp_speed (vel: Vec2 <Fx32>, max_speed: Option <Fx32>) -> Vec2 <Fx32> {
+	match max_speed {
+		Some (max_speed) if vel.length_64 () > max_speed => {
+			Vec2::<Fx32>::from (vel.normalized_64 ()).scale_64 (max_speed)
+		},
+		_ => vel,
+	}
+}
+
+/*
+Advances a particle by dt ticks, which must be more than 0 and no
+more than 1.
+
+Velocities are always in units per tick. Gravity is added before the
+particle moves, so a step of dt is off from the same time split into
+smaller steps by gravity * dt^2 / 4 at most, before rounding.
+
+on_subtick is called after every trace with the particle, the time
+used so far this step, the velocity it had going into the trace, and
+the trace result.
+
+shrink is how much smaller the particle is than the radius the
+obstacles were inflated by, like for Broadphase::trace_shrunk.
+
+scratch holds the broadphase's candidates between traces. Pass the
+same one for every particle, so stepping doesn't allocate.
+
+Returns the part of dt that was left over when the subticks ran out.
+Ideally it's always 0.
+*/
+pub fn step_particle <F> (world: &Broadphase, scratch: &mut Vec <u32>, config: &PhysicsConfig, particle: &mut Ray2, material: &Material, shrink: Fx32, dt: Fx32, mut on_subtick: F) -> Fx32 
+where F: FnMut (&Ray2, Fx32, Vec2 <Fx32>, &Ray2TraceResult) 
+{
+	let mut remaining_dt = dt;
+	let mut contacts = ContactSet::new ();
+	
+	let gravity = config.gravity.scale_64 (dt);
+	let mut p = Ray2::new (particle.start, clamp_speed (particle.get_dir () + gravity, config.max_speed));
+	
+	for _ in 0..config.subticks {
+		let vel = p.get_dir ();
+		
+		let trace_result = {
+			let dt_particle = p.apply_dt (remaining_dt.to_small ());
+			
+			world.trace_shrunk_in (&dt_particle, shrink, scratch, |result| contacts.filter (result, vel))
+		};
+		
+		match trace_result {
+			Ray2TraceResult::Miss => {
+				p.start = p.start + (vel * remaining_dt);
+				// Consume the entire remaining tick timestep
+				remaining_dt = Fx32::from_int (0);
+			},
+			Ray2TraceResult::Pop (ref c) => {
+				let normal = Vec2::<Fx32>::from (c.normal).normalized_64 ();
+				// Pops don't bounce off the materials, only by however
+				// much the config says
+				let material = Material {
+					restitution: config.pop_restitution,
+					.. material.combine (&c.material)
+				};
+				
+				contacts.add (normal);
+				p = Ray2::new (c.pos, contacts.constrain (material.respond (vel, normal)));
+				
+				// Consume no time - This may lead to time dilation
+				// for some objects if we run short of CPU
+			},
+			Ray2TraceResult::Hit (ref c) => {
+				let normal = Vec2::<Fx32>::from (c.normal).normalized_64 ();
+				let mut material = material.combine (&c.material);
+				material.restitution = material.restitution.x.mul_64 (config.restitution.x).to_small ();
+				if material.min_bounce_speed < config.contact_speed {
+					material.min_bounce_speed = config.contact_speed;
+				}
+				
+				contacts.add (normal);
+				p = Ray2::new (c.pos, contacts.constrain (material.respond (vel, normal)));
+				
+				// t is along the ray for the time that was left, so this
+				// works for any dt
+				let consumed_time = remaining_dt * Fx32::from (c.t);
+				remaining_dt = remaining_dt - consumed_time;
+			},
+		};
+		
+		on_subtick (&p, dt - remaining_dt, vel, &trace_result);
+		
+		if remaining_dt <= Fx32::from_int (0) {
+			break;
+		}
+	}
+	
+	*particle = p;
+	
+	remaining_dt
+}
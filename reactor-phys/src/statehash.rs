@@ -0,0 +1,60 @@
+use cherenkov::CherenkovSim;
+use snapshot::write_state;
+use snapshot::write_world;
+
+use std::io;
+use std::io::prelude::*;
+
+/*
+A checksum of a sim, for lockstep games to compare every tick and
+catch desyncs early.
+
+It hashes the same little-endian bytes a snapshot is made of, with
+64-bit FNV-1a, so it only depends on the Fx32 bits and counts, never
+on pointers or Vec capacities, and it's the same on every platform.
+std's Hasher makes no such promise.
+
+Hashing the obstacles costs more than the particles in most levels,
+and they rarely change mid-game, so they're optional.
+*/
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+pub struct Fnv1a {
+	pub hash: u64,
+}
+
+impl Fnv1a {
+	pub fn new () -> Fnv1a {
+		Fnv1a {
+			hash: FNV_OFFSET,
+		}
+	}
+}
+
+impl Write for Fnv1a {
+	fn write (&mut self, buf: &[u8]) -> io::Result <usize> {
+		for b in buf.iter () {
+			self.hash = (self.hash ^ *b as u64).wrapping_mul (FNV_PRIME);
+		}
+		
+		Ok (buf.len ())
+	}
+	
+	fn flush (&mut self) -> io::Result <()> {
+		Ok (())
+	}
+}
+
+pub fn state_hash (sim: &CherenkovSim, obstacles: bool) -> u64 {
+	let mut h = Fnv1a::new ();
+	
+	// Writing to Fnv1a never fails
+	if obstacles {
+		write_world (&mut h, &sim.world).unwrap ();
+	}
+	write_state (&mut h, sim).unwrap ();
+	
+	h.hash
+}
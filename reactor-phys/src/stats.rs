@@ -0,0 +1,82 @@
+use fx32::Fx32;
+use raytraceresult::Ray2TraceResult;
+
+use std::cmp;
+
+/*
+Counters for the debug overlay. CherenkovSim adds every step to its
+own StepStats until something resets it, so reset once a frame to
+see per-frame numbers, or less often to see totals.
+
+Everything is a plain sum or max, so the parallel step gets the same
+numbers as the serial one no matter how the work is split up. Sums
+stop at u32::MAX instead of overflowing, for sims that never reset.
+*/
+#[derive (Clone, Copy, Debug, Eq, PartialEq)]
+pub struct StepStats {
+	// Awake particles stepped. Sleeping ones are skipped.
+	pub particles: u32,
+	// One per subtick
+	pub traces: u32,
+	pub hits: u32,
+	pub pops: u32,
+	// The most subticks any particle needed in one tick. If it's near
+	// config.subticks, particles are getting stuck.
+	pub max_subticks: u32,
+	// Ticks that ran out of subticks before they ran out of time
+	pub slips: u32,
+	// The deepest pop, so how far particles ended up inside obstacles
+	pub max_depth: Fx32,
+}
+
+impl Default for StepStats {
+	fn default () -> StepStats {
+		StepStats {
+			particles: 0,
+			traces: 0,
+			hits: 0,
+			pops: 0,
+			max_subticks: 0,
+			slips: 0,
+			max_depth: Fx32::from_int (0),
+		}
+	}
+}
+
+impl StepStats {
+	// Call after each trace
+	pub fn record (&mut self, result: &Ray2TraceResult) {
+		self.traces = self.traces.saturating_add (1);
+		
+		match *result {
+			Ray2TraceResult::Hit (..) => self.hits = self.hits.saturating_add (1),
+			Ray2TraceResult::Pop (ref c) => {
+				self.pops = self.pops.saturating_add (1);
+				self.max_depth = Fx32 { x: cmp::max (self.max_depth.x, c.depth.x) };
+			},
+			Ray2TraceResult::Miss => (),
+		}
+	}
+	
+	// Call once a particle's tick is done, with the time step_particle
+	// had left over
+	pub fn finish_particle (&mut self, remaining_dt: Fx32) {
+		self.particles = 1;
+		self.max_subticks = self.traces;
+		if remaining_dt > Fx32::from_int (0) {
+			self.slips = 1;
+		}
+	}
+	
+	pub fn add (self, o: StepStats) -> StepStats {
+		StepStats {
+			particles: self.particles.saturating_add (o.particles),
+			traces: self.traces.saturating_add (o.traces),
+			hits: self.hits.saturating_add (o.hits),
+			pops: self.pops.saturating_add (o.pops),
+			max_subticks: cmp::max (self.max_subticks, o.max_subticks),
+			slips: self.slips.saturating_add (o.slips),
+			max_depth: Fx32 { x: cmp::max (self.max_depth.x, o.max_depth.x) },
+		}
+	}
+}
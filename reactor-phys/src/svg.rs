@@ -0,0 +1,155 @@
+use arc::Arc;
+use fx32::Fx32;
+use polycapsule::PolyCapsule;
+use raytraceresult::Ray2TraceResult;
+use scenario::ScenarioResult;
+use vec2::Vec2;
+use wide_line::WideLine;
+
+use std::f64;
+use std::io;
+use std::io::prelude::*;
+
+/*
+Draws a level and what the particles did in it, so physics changes
+can be compared by eye.
+
+Obstacles are grey, drawn at their full radius. Each line is a
+rectangle and each arc is its whole circle, since Arc::filter_normal
+doesn't use rejected_normals yet and every arc collides all the way
+round. Paths go
+from blue at spawn to red at the last tick. Hits are marked with
+green dots and pops with orange ones, each with a line along the
+normal.
+*/
+
+// Rounded to keep the file small. Nobody can see a hundredth of a
+// pixel anyway.
+fn round (x: f64) -> f64 {
+	(x * 100.0).round () / 100.0
+}
+
+fn point (v: Vec2 <Fx32>) -> (f64, f64) {
+	(round (v.x.to_f64 ()), round (v.y.to_f64 ()))
+}
+
+fn write_line_shape <T> (writer: &mut T, line: &WideLine) -> io::Result <()> where T: Write {
+	let (x0, y0) = point (line.start);
+	let (x1, y1) = point (line.end);
+	let r = line.radius.to_f64 ();
+	
+	let length = (x1 - x0).hypot (y1 - y0);
+	if length == 0.0 {
+		return Ok (());
+	}
+	let (nx, ny) = (-(y1 - y0) / length * r, (x1 - x0) / length * r);
+	
+	write! (writer, "<polygon class=\"obstacle\" points=\"{},{} {},{} {},{} {},{}\"/>\n",
+		round (x0 + nx), round (y0 + ny), round (x1 + nx), round (y1 + ny),
+		round (x1 - nx), round (y1 - ny), round (x0 - nx), round (y0 - ny))
+}
+
+fn write_arc_shape <T> (writer: &mut T, arc: &Arc) -> io::Result <()> where T: Write {
+	let (cx, cy) = point (arc.circle.center);
+	let r = round (arc.circle.radius.to_f64 ());
+	
+	write! (writer, "<circle class=\"obstacle\" cx=\"{}\" cy=\"{}\" r=\"{}\"/>\n", cx, cy, r)
+}
+
+fn write_contact <T> (writer: &mut T, result: &Ray2TraceResult, normal_length: f64) -> io::Result <()> where T: Write {
+	let class = match *result {
+		Ray2TraceResult::Hit (..) => "hit",
+		Ray2TraceResult::Pop (..) => "pop",
+		Ray2TraceResult::Miss => return Ok (()),
+	};
+	let c = result.contact ().unwrap ();
+	
+	let (x, y) = point (c.pos);
+	let (nx, ny) = point (c.normal.into ());
+	
+	write! (writer, "<circle class=\"{}\" cx=\"{}\" cy=\"{}\" r=\"1\"/>\n", class, x, y)?;
+	write! (writer, "<line class=\"{}\" x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\"/>\n",
+		class, x, y, round (x + nx * normal_length), round (y + ny * normal_length))
+}
+
+pub fn write_svg <T> (writer: &mut T, obstacles: &[PolyCapsule], result: &ScenarioResult) -> io::Result <()> where T: Write {
+	// Fit the view around everything
+	let mut min = (f64::INFINITY, f64::INFINITY);
+	let mut max = (f64::NEG_INFINITY, f64::NEG_INFINITY);
+	{
+		let mut grow = |(x, y): (f64, f64)| {
+			min = (min.0.min (x), min.1.min (y));
+			max = (max.0.max (x), max.1.max (y));
+		};
+		
+		for capsule in obstacles.iter () {
+			let (a, b) = capsule.bounds ();
+			grow (point (a));
+			grow (point (b));
+		}
+		for trajectory in result.trajectories.iter () {
+			for p in trajectory.iter () {
+				grow (point (p.pos));
+			}
+		}
+	}
+	if min.0 > max.0 {
+		// Nothing to draw
+		min = (0.0, 0.0);
+		max = (0.0, 0.0);
+	}
+	let margin = 16.0;
+	let (x, y) = (min.0 - margin, min.1 - margin);
+	let (w, h) = (max.0 - min.0 + margin * 2.0, max.1 - min.1 + margin * 2.0);
+	
+	let last_clock = result.trajectories.iter ()
+	.filter_map (|t| t.last ())
+	.map (|p| p.clock.to_f64 ())
+	.fold (1.0, f64::max);
+	
+	write! (writer, "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"{} {} {} {}\">\n", w, h, x, y, w, h)?;
+	write! (writer, "<style>\n")?;
+	write! (writer, ".obstacle {{ fill: #888; fill-opacity: 0.5; stroke: none; }}\n")?;
+	write! (writer, ".centerline {{ stroke: #444; stroke-width: 0.5; fill: none; }}\n")?;
+	write! (writer, ".path {{ stroke-width: 0.5; fill: none; }}\n")?;
+	write! (writer, ".hit {{ fill: #0a0; stroke: #0a0; stroke-width: 0.5; }}\n")?;
+	write! (writer, ".pop {{ fill: #f80; stroke: #f80; stroke-width: 0.5; }}\n")?;
+	write! (writer, "</style>\n")?;
+	write! (writer, "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"white\"/>\n", x, y, w, h)?;
+	
+	let mut normal_length = 4.0f64;
+	
+	for capsule in obstacles.iter () {
+		for line in capsule.lines.iter () {
+			write_line_shape (writer, line)?;
+			normal_length = normal_length.max (line.radius.to_f64 () / 2.0);
+		}
+		for arc in capsule.arcs.iter () {
+			write_arc_shape (writer, arc)?;
+		}
+		for line in capsule.lines.iter () {
+			let (x0, y0) = point (line.start);
+			let (x1, y1) = point (line.end);
+			
+			write! (writer, "<line class=\"centerline\" x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\"/>\n", x0, y0, x1, y1)?;
+		}
+	}
+	
+	for trajectory in result.trajectories.iter () {
+		for pair in trajectory.windows (2) {
+			let (x0, y0) = point (pair [0].pos);
+			let (x1, y1) = point (pair [1].pos);
+			let hue = 240.0 * (1.0 - pair [1].clock.to_f64 () / last_clock);
+			
+			write! (writer, "<line class=\"path\" x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"hsl({:.0},100%,45%)\"/>\n", x0, y0, x1, y1, hue)?;
+		}
+	}
+	
+	for trajectory in result.trajectories.iter () {
+		for p in trajectory.iter () {
+			write_contact (writer, &p.result, normal_length)?;
+		}
+	}
+	
+	write! (writer, "</svg>\n")
+}
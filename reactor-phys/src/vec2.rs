@@ -103,6 +103,42 @@ impl <Real: Into <Fx32> + From <Fx32> + From <Fx32Small> + Neg + Mul <Fx32> + Mu
 	}
 }
 
+/*
+Slower versions that do all their math in 64 bits.
+
+The generic versions above drop up to 14 bits before multiplying,
+so velocities under 1/4 can vanish from a dot product. That's fine
+for tracing but not for contact response.
+*/
+impl Vec2 <Fx32> {
+	pub fn dot_64 (self, o: Vec2 <Fx32>) -> Fx32 {
+		self.x.mul_64 (o.x) + self.y.mul_64 (o.y)
+	}
+	
+	pub fn scale_64 (self, s: Fx32) -> Vec2 <Fx32> {
+		Vec2::<Fx32> {
+			x: self.x.mul_64 (s),
+			y: self.y.mul_64 (s),
+		}
+	}
+	
+	pub fn length_64 (self) -> Fx32 {
+		Fx32::hypot_64 (self.x, self.y)
+	}
+	
+	pub fn normalized_64 (self) -> Vec2 <Fx32Small> {
+		let length = self.length_64 ();
+		if length == 0 {
+			return self.to_small ();
+		}
+		
+		Vec2::<Fx32> {
+			x: self.x.div_64 (length),
+			y: self.y.div_64 (length),
+		}.to_small ()
+	}
+}
+
 impl From <Vec2 <Fx32Small>> for Vec2 <Fx32> {
 	fn from (o: Vec2 <Fx32Small>) -> Vec2 <Fx32> {
 		Vec2::<Fx32> {
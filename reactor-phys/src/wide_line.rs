@@ -3,6 +3,7 @@
 
 use fx32::Fx32;
 use fx32::Fx32Small;
+use material::Material;
 use vec2::Vec2;
 
 #[derive (Clone, Copy, Debug)]
@@ -11,30 +12,31 @@ pub struct WideLine {
 	pub end: Vec2 <Fx32>,
 	pub radius: Fx32,
 	pub line_tangent: Vec2 <Fx32Small>,
+	// If set, only the side this normal points to is solid.
+	// Anything coming from the other side passes through.
+	pub one_way: Option <Vec2 <Fx32Small>>,
+	pub material: Material,
 }
 
 impl WideLine {
 	pub fn new (start: Vec2 <Fx32>, end: Vec2 <Fx32>, radius: Fx32) -> WideLine 
 	{
-		let line_tangent: Vec2 <Fx32> = end - start;
-		// TODO: Use 64-bit normalize since this is cached
-		let line_tangent = Vec2::<Fx32> {
-			x: line_tangent.x * Fx32::from_q (1, 256),
-			y: line_tangent.y * Fx32::from_q (1, 256),
-		}.normalized ();
+		let line_tangent = (end - start).normalized_64 ();
 		
 		WideLine {
 			start: start,
 			end: end,
 			radius: radius,
 			line_tangent: line_tangent,
+			one_way: None,
+			material: Material::default (),
 		}
 	}
 }
-/*
-impl Circle {
-	pub fn signed_distance (&self, p: &Vec2 <Fx32>) -> Fx32 {
-		(*p - self.center).length () - self.radius
-	}
+
+// How deep a particle may sink into a one-way platform and still be
+// popped back out on top. Anything deeper is assumed to be jumping
+// up through it, e.g. if it ended last tick halfway through.
+pub fn one_way_slop (radius: Fx32) -> Fx32 {
+	Fx32 { x: radius.x / 4 }
 }
-*/
@@ -0,0 +1,47 @@
+use broadphase::Broadphase;
+use fx32::Fx32;
+use polycapsule::PolyCapsule;
+
+/*
+The obstacles, and the broadphase built from them.
+
+Sims keep their World in a sync::Arc, so replays, ghosts and
+prediction sims can all share one copy of a level. A sim that changes
+its obstacles gets its own copy first, and the others keep the one
+they had.
+*/
+#[derive (Clone)]
+pub struct World {
+	// Every obstacle is inflated by this, so particles this size are
+	// the cheapest to trace
+	pub radius: Fx32,
+	// If you change these directly, set broadphase to None and build
+	pub obstacles: Vec <PolyCapsule>,
+	// Rebuilt from obstacles by build
+	pub broadphase: Option <Broadphase>,
+}
+
+impl World {
+	pub fn new (radius: Fx32) -> World {
+		World {
+			radius: radius,
+			obstacles: vec! [],
+			broadphase: None,
+		}
+	}
+	
+	pub fn add_obstacle (&mut self, capsule: PolyCapsule) {
+		self.obstacles.push (capsule);
+		self.broadphase = None;
+	}
+	
+	pub fn build (&mut self) {
+		if self.broadphase.is_none () {
+			self.broadphase = Some (Broadphase::new (&self.obstacles));
+		}
+	}
+	
+	pub fn is_built (&self) -> bool {
+		self.broadphase.is_some ()
+	}
+}